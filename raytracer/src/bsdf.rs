@@ -0,0 +1,255 @@
+// The scattering interface of materials
+// A material hands out a Bsdf for each hit; sampling and evaluation happen in the local
+// shading frame: z along the shading normal, directions pointing away from the surface and
+// wo towards the viewer. f is the BSDF times |cos(wi)|, so a path is weighted by f / pdf.
+
+use crate::hittable::HitRecord;
+use crate::material::*;
+use crate::onb::*;
+use crate::ray::*;
+use crate::vec3::Vec3;
+use std::ops::BitOr;
+
+// the kinds of lobes a sample came from, or a BSDF has
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BsdfFlags(u8);
+impl BsdfFlags {
+    pub const REFLECTION: Self = Self(1);
+    pub const TRANSMISSION: Self = Self(1 << 1);
+    pub const DIFFUSE: Self = Self(1 << 2);
+    pub const GLOSSY: Self = Self(1 << 3);
+    // a delta lobe: eval and pdf never see it, the pdf of its samples is the chance of
+    // picking it; with GLOSSY too it is spread out around the delta (fuzzy Metal), sampled
+    // the same way but no sharp mirror
+    pub const SPECULAR: Self = Self(1 << 4);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn is_specular(self) -> bool {
+        self.contains(Self::SPECULAR)
+    }
+    // a delta lobe that is not spread out: ray differentials follow it
+    pub fn is_sharp(self) -> bool {
+        self.is_specular() && !self.contains(Self::GLOSSY)
+    }
+    // only delta lobes, which a light sample never hits
+    pub fn is_specular_only(self) -> bool {
+        !self.contains(Self::DIFFUSE) && !self.contains(Self::GLOSSY)
+    }
+}
+impl BitOr for BsdfFlags {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+pub struct BsdfSample {
+    pub wi: Vec3,
+    pub f: Vec3,
+    pub pdf: f64,
+    pub flags: BsdfFlags,
+}
+impl BsdfSample {
+    pub fn new(wi: Vec3, f: Vec3, pdf: f64, flags: BsdfFlags) -> Self {
+        Self { wi, f, pdf, flags }
+    }
+}
+
+// the BSDF of a material at one hit point
+pub struct Bsdf<'a> {
+    pub material: &'a dyn Material,
+    pub rec: &'a HitRecord,
+    pub frame: ONB,
+    pub flags: BsdfFlags, // all the lobes
+    // n_t / n_i across a refracting surface, n_i is on the side of wo
+    pub eta: f64,
+    pub n_i: f64,
+    // the nested dielectric whose boundary this is, transmitted rays enter or leave it
+    pub boundary: Option<NestedMedium>,
+    // a false boundary of a nested dielectric: the ray goes on straight
+    pub pass_through: bool,
+    // the path state after the hit, dispersion fixes the wavelength
+    pub wavelength: Option<f64>,
+    pub dispersed: bool,
+    // applied to everything the BSDF returns, e.g. for the choice of a wavelength
+    pub weight: Vec3,
+    // a clear layer on top of the material, see CoatedMaterial
+    pub coat: Option<Coating>,
+    // the frame's normal is bent away from the geometric one (bump.rs): directions on
+    // different sides of the two are dropped, they would leak through the surface
+    pub perturbed: bool,
+}
+impl<'a> Bsdf<'a> {
+    pub fn new(
+        material: &'a dyn Material,
+        ray_in: &Ray,
+        rec: &'a HitRecord,
+        flags: BsdfFlags,
+    ) -> Self {
+        Self {
+            material,
+            rec,
+            frame: shading_frame(rec),
+            flags,
+            eta: 1.0,
+            n_i: 1.0,
+            boundary: None,
+            pass_through: false,
+            wavelength: ray_in.wavelength,
+            dispersed: ray_in.dispersed,
+            weight: Vec3::ones(),
+            coat: None,
+            perturbed: false,
+        }
+    }
+
+    pub fn to_local(&self, w: Vec3) -> Vec3 {
+        self.frame.to_local(&w)
+    }
+    pub fn to_world(&self, w: Vec3) -> Vec3 {
+        self.frame.local(&w)
+    }
+    // whether local w lies on the same side of the geometric normal as of the shading one
+    fn consistent(&self, w: Vec3) -> bool {
+        !self.perturbed || (self.to_world(w) * self.rec.normal > 0.0) == (w.z > 0.0)
+    }
+
+    // u: uniform random numbers, the first picks a lobe, the others a direction in it
+    pub fn sample(&self, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        if self.pass_through {
+            return Some(BsdfSample::new(
+                -wo,
+                self.weight,
+                1.0,
+                BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION,
+            ));
+        }
+        let mut sample = match self.coat {
+            Some(coat) => coat.sample(self, wo, u),
+            None => self.material.sample(self, wo, u),
+        }?;
+        if !self.consistent(sample.wi) {
+            return None;
+        }
+        sample.f = sample.f.elemul(self.weight);
+        Some(sample)
+    }
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if self.pass_through || !self.consistent(wi) {
+            return Vec3::zero();
+        }
+        match self.coat {
+            Some(coat) => coat.eval(self, wo, wi),
+            None => self.material.eval(self, wo, wi),
+        }
+        .elemul(self.weight)
+    }
+    pub fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if self.pass_through || !self.consistent(wi) {
+            return 0.0;
+        }
+        match self.coat {
+            Some(coat) => coat.pdf(self, wo, wi),
+            None => self.material.pdf(self, wo, wi),
+        }
+    }
+
+    // make this the boundary of a nested dielectric, see boundary_iors
+    // index-matched boundaries are passed straight through unless keep_matched, e.g. for a film
+    // that still reflects there
+    pub fn set_boundary(&mut self, me: NestedMedium, ray_in: &Ray, keep_matched: bool) {
+        match boundary_iors(&me, ray_in, self.wavelength) {
+            Some((n_i, n_t)) => {
+                self.eta = n_t / n_i;
+                self.n_i = n_i;
+                self.pass_through = !keep_matched && (n_i - n_t).abs() < 1e-9;
+            }
+            None => self.pass_through = true,
+        }
+        self.boundary = Some(me);
+    }
+
+    // the differential of the ray leaving towards local wi by a specular reflection or
+    // refraction, the offset rays turned by normals that bend with the surface as in pbrt-v3
+    // None where the hittable gives no dpdu and dpdv: its curvature is unknown
+    pub fn specular_differential(&self, ray_in: &Ray, wi: Vec3) -> Option<RayDifferential> {
+        let d = ray_in.differential?;
+        let rec = self.rec;
+        if rec.dpdu.squared_length() == 0.0 || rec.dpdv.squared_length() == 0.0 {
+            return None;
+        }
+        let (px, py) = rec.differential_hits(ray_in)?;
+        let wo = -ray_in.dir.unit();
+        let wi = self.to_world(wi);
+        // the normal on the side of wo, and how it changes across the footprint
+        let n = if wo * self.frame.w > 0.0 {
+            self.frame.w
+        } else {
+            -self.frame.w
+        };
+        let outward = if (n * rec.normal > 0.0) == rec.front_face {
+            1.0
+        } else {
+            -1.0
+        };
+        let fp = &rec.footprint;
+        let dndx = (rec.dndu * fp.dudx + rec.dndv * fp.dvdx) * outward;
+        let dndy = (rec.dndu * fp.dudy + rec.dndv * fp.dvdy) * outward;
+        let cos_o = wo * n;
+        let turn = |dir: Vec3, dn: Vec3| {
+            let dwo = -dir.unit() - wo;
+            let ddn = dwo * n + wo * dn;
+            if wi * n > 0.0 {
+                wi - dwo + (dn * cos_o + n * ddn) * 2.0
+            } else {
+                // n_i / n_t = 1 / eta
+                let eta = 1.0 / self.eta;
+                let cos_t = -(wi * n);
+                let mu = eta * cos_o - cos_t;
+                let dmu = (eta - eta * eta * cos_o / cos_t) * ddn;
+                wi - dwo * eta + dn * mu + n * dmu
+            }
+        };
+        Some(RayDifferential {
+            rx_origin: px,
+            rx_dir: turn(d.rx_dir, dndx),
+            ry_origin: py,
+            ry_dir: turn(d.ry_dir, dndy),
+        })
+    }
+
+    // the ray leaving the hit towards local wi
+    pub fn spawn(&self, ray_in: &Ray, wi: Vec3) -> Ray {
+        let dir = self.to_world(wi);
+        let mut ret = ray_in.spawn(self.rec.p, dir);
+        ret.wavelength = self.wavelength;
+        ret.dispersed = self.dispersed;
+        if let Some(me) = &self.boundary {
+            if dir * self.rec.normal < 0.0 {
+                match ret.media.iter().position(|m| m.id == me.id) {
+                    Some(idx) => {
+                        ret.media.remove(idx);
+                    }
+                    None => ret.media.push(me.clone()),
+                }
+            }
+        }
+        ret
+    }
+}
+
+// the hemisphere a local direction is in
+pub fn side(w: Vec3) -> BsdfFlags {
+    if w.z > 0.0 {
+        BsdfFlags::REFLECTION
+    } else {
+        BsdfFlags::TRANSMISSION
+    }
+}
+
+// reflect about the normal of the local frame
+pub fn reflect_z(w: Vec3) -> Vec3 {
+    Vec3::new(-w.x, -w.y, w.z)
+}
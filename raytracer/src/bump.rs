@@ -0,0 +1,162 @@
+// Bump and normal mapping
+// Both bend the shading normal of any material without moving the surface: a normal map
+// gives the new normal in the tangent space of the hit (x along u, y along v, z out), a bump
+// map a height whose slope tilts the normal (Blinn 1978, the differences as in pbrt-v3).
+// The bent normal may face away from the viewer or reflect below the surface, which shows as
+// black spots; it is pulled back until the mirror reflection clears the geometric normal,
+// as Cycles does, and directions on different sides of the two normals are dropped
+// (Bsdf::perturbed).
+
+use crate::bsdf::*;
+use crate::hittable::HitRecord;
+use crate::material::*;
+use crate::onb::*;
+use crate::ray::*;
+use crate::texture::*;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// the step of the finite differences, in u and v
+const DELTA: f64 = 0.0005;
+
+// the surface directions along u and v at the hit, from the hittable where it has them
+fn surface_tangents(rec: &HitRecord) -> (Vec3, Vec3) {
+    if rec.dpdu.squared_length() > 0.0 && rec.dpdv.squared_length() > 0.0 {
+        (rec.dpdu, rec.dpdv)
+    } else {
+        let frame = shading_frame(rec);
+        (frame.u, frame.v)
+    }
+}
+
+// the normal closest to n whose mirror reflection of wo (towards the viewer) is above the
+// geometric normal ng, after Cycles' ensure_valid_reflection
+pub fn valid_reflection_normal(ng: Vec3, wo: Vec3, n: Vec3) -> Vec3 {
+    let r = n * (2.0 * (n * wo)) - wo;
+    // some room above the surface, less at grazing angles
+    let threshold = (0.9 * (ng * wo)).min(0.01);
+    if r * ng >= threshold {
+        return n;
+    }
+    // look for the normal in the plane of n and ng: x across, z along ng
+    let x = n - ng * (n * ng);
+    if x.squared_length() < 1e-12 {
+        return ng;
+    }
+    let x = x.unit();
+    let (ix, iz) = (wo * x, wo * ng);
+    let (ix2, iz2) = (ix * ix, iz * iz);
+    let a = ix2 + iz2;
+    let b = (ix2 * (a - threshold * threshold)).max(0.0).sqrt();
+    let c = iz * threshold + a;
+    // the two roots for the squared z of the normal
+    let roots = [0.5 * (b + c) / a, 0.5 * (c - b) / a];
+    let valid = |nz2: f64| nz2 > 1e-5 && nz2 <= 1.0 + 1e-5;
+    let normal = |nz2: f64| ((1.0 - nz2).max(0.0).sqrt(), nz2.max(0.0).sqrt());
+    let (nx, nz) = match (valid(roots[0]), valid(roots[1])) {
+        (true, true) => {
+            let (n1, n2) = (normal(roots[0]), normal(roots[1]));
+            let r1 = 2.0 * (n1.0 * ix + n1.1 * iz) * n1.1 - iz;
+            let r2 = 2.0 * (n2.0 * ix + n2.1 * iz) * n2.1 - iz;
+            // both clear the surface: the lower reflection bends the least, else the higher
+            let prefer_first = if r1 >= 1e-5 && r2 >= 1e-5 {
+                r1 < r2
+            } else {
+                r1 > r2
+            };
+            if prefer_first {
+                n1
+            } else {
+                n2
+            }
+        }
+        (true, false) => normal(roots[0]),
+        (false, true) => normal(roots[1]),
+        (false, false) => return ng,
+    };
+    x * nx + ng * nz
+}
+
+// the BSDF of base with its frame turned to the normal n
+fn bend<'a>(base: &'a dyn Material, ray_in: &Ray, rec: &'a HitRecord, n: Vec3) -> Option<Bsdf<'a>> {
+    let mut bsdf = base.bsdf(ray_in, rec)?;
+    let n = valid_reflection_normal(rec.normal, -ray_in.dir.unit(), n.unit());
+    bsdf.frame = ONB::build_from_w_and_tangent(&n, &bsdf.frame.u);
+    bsdf.perturbed = true;
+    Some(bsdf)
+}
+
+// A tangent-space normal map over any material, e.g. an ImageTexture: each channel from
+// [0, 1] to [-1, 1], blue pointing out of the surface, green along v
+pub struct NormalMapped {
+    pub base: Arc<dyn Material>,
+    pub map: Arc<dyn Texture>,
+}
+impl Material for NormalMapped {
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        let (dpdu, dpdv) = surface_tangents(rec);
+        let n = rec.normal;
+        let t = (dpdu - n * (dpdu * n)).unit();
+        // the bitangent follows v whichever side the normal was flipped to
+        let mut b = n.cross(t);
+        if b * dpdv < 0.0 {
+            b = -b;
+        }
+        let m = self.map.filtered(rec.u, rec.v, rec.p, &rec.footprint) * 2.0 - Vec3::ones();
+        bend(&*self.base, ray_in, rec, t * m.x + b * m.y + n * m.z)
+    }
+    fn opaque(&self, rec: &HitRecord) -> bool {
+        self.base.opaque(rec)
+    }
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Vec3) -> Vec3 {
+        self.base.emitted(ray_in, rec, u, v, p)
+    }
+}
+impl NormalMapped {
+    pub fn new(base: Arc<dyn Material>, map: Arc<dyn Texture>) -> Self {
+        Self { base, map }
+    }
+}
+
+// A bump map over any material: the height is the first channel of a texture times scale,
+// in the units of the scene, e.g. a NoiseTexture for a rough plaster
+pub struct BumpMapped {
+    pub base: Arc<dyn Material>,
+    pub height: Arc<dyn Texture>,
+    pub scale: f64,
+}
+impl Material for BumpMapped {
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        let (dpdu, dpdv) = surface_tangents(rec);
+        // raised along the outward normal, seen from either side
+        let outward = if rec.front_face {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+        let height = |u: f64, v: f64, p: Vec3| self.height.value(u, v, p).x * self.scale;
+        let h = height(rec.u, rec.v, rec.p);
+        let dhdu = (height(rec.u + DELTA, rec.v, rec.p + dpdu * DELTA) - h) / DELTA;
+        let dhdv = (height(rec.u, rec.v + DELTA, rec.p + dpdv * DELTA) - h) / DELTA;
+        let mut n = (dpdu + outward * dhdu).cross(dpdv + outward * dhdv);
+        if n * rec.normal < 0.0 {
+            n = -n;
+        }
+        bend(&*self.base, ray_in, rec, n)
+    }
+    fn opaque(&self, rec: &HitRecord) -> bool {
+        self.base.opaque(rec)
+    }
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Vec3) -> Vec3 {
+        self.base.emitted(ray_in, rec, u, v, p)
+    }
+}
+impl BumpMapped {
+    pub fn new(base: Arc<dyn Material>, height: Arc<dyn Texture>, scale: f64) -> Self {
+        Self {
+            base,
+            height,
+            scale,
+        }
+    }
+}
@@ -63,6 +63,30 @@ impl AABB {
         true
     }
 
+    // the parametric interval [t_enter, t_exit] the ray spends inside the box
+    pub fn hit_range(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let mut tmin = t_min;
+        let mut tmax = t_max;
+        let orig = [r.orig.x, r.orig.y, r.orig.z];
+        let dir = [r.dir.x, r.dir.y, r.dir.z];
+        let mn = [self._min.x, self._min.y, self._min.z];
+        let mx = [self._max.x, self._max.y, self._max.z];
+        for a in 0..3 {
+            let invd = 1.0 / dir[a];
+            let mut t0 = (mn[a] - orig[a]) * invd;
+            let mut t1 = (mx[a] - orig[a]) * invd;
+            if invd < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            tmin = if t0 > tmin { t0 } else { tmin };
+            tmax = if t1 < tmax { t1 } else { tmax };
+            if tmax <= tmin {
+                return None;
+            }
+        }
+        Some((tmin, tmax))
+    }
+
     pub fn surrounding_box(box0: Self, box1: Self) -> Self {
         Self::new(
             Vec3::new(
@@ -13,6 +13,8 @@ pub struct Camera {
     _w: Vec3,
 
     len_radius: f64,
+    // the spacing of the samples in s and t, zero: no ray differentials
+    sample_spacing: (f64, f64),
 }
 impl Camera {
     // lookfrom: the point you look from, lookat: the same
@@ -50,15 +52,37 @@ impl Camera {
             _w: w,
 
             len_radius: aperture / 2.0,
+            sample_spacing: (0.0, 0.0),
         }
     }
 
+    // turns on ray differentials for an image of width x height pixels, s and t running from 0
+    // to 1 across the pixel centers; the footprint of a sample shrinks with more samples per
+    // pixel, to an eighth of a pixel at most (as in pbrt)
+    pub fn set_resolution(&mut self, width: u32, height: u32, sample_per_pixel: u32) {
+        let scale = (1.0 / (sample_per_pixel as f64).sqrt()).max(0.125);
+        self.sample_spacing = (
+            scale / (width.max(2) - 1) as f64,
+            scale / (height.max(2) - 1) as f64,
+        );
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
         let rd = Vec3::rand_in_unit_disk() * self.len_radius;
         let offset = self.u * rd.x + self.v * rd.y;
-        Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
-        )
+        let dir =
+            self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset;
+        let mut ret = Ray::new(self.origin + offset, dir);
+        let (ds, dt) = self.sample_spacing;
+        if ds > 0.0 {
+            // through the same point on the lens, focused on the neighboring samples
+            ret.differential = Some(RayDifferential {
+                rx_origin: ret.orig,
+                rx_dir: dir + self.horizontal * ds,
+                ry_origin: ret.orig,
+                ry_dir: dir + self.vertical * dt,
+            });
+        }
+        ret
     }
 }
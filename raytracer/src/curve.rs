@@ -0,0 +1,341 @@
+// Cubic Bézier curves for hair, fur and grass
+// The curve is moved into a frame where the ray runs along +z, then recursively split
+// until each piece is flat enough to be tested as a straight segment
+
+use crate::bvh::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::onb::*;
+use crate::ray::Ray;
+use crate::shared_tools::*;
+use crate::texture::Footprint;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CurveMode {
+    Ribbon,   // flat, always facing the ray
+    Cylinder, // a thick tube
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a * (1.0 - t) + b * t
+}
+
+fn eval_bezier(cp: &[Vec3; 4], s: f64) -> Vec3 {
+    let cp01 = cp[0] * (1.0 - s) + cp[1] * s;
+    let cp12 = cp[1] * (1.0 - s) + cp[2] * s;
+    let cp23 = cp[2] * (1.0 - s) + cp[3] * s;
+    let cp012 = cp01 * (1.0 - s) + cp12 * s;
+    let cp123 = cp12 * (1.0 - s) + cp23 * s;
+    cp012 * (1.0 - s) + cp123 * s
+}
+
+fn bezier_derivative(cp: &[Vec3; 4], s: f64) -> Vec3 {
+    ((cp[1] - cp[0]) * ((1.0 - s) * (1.0 - s))
+        + (cp[2] - cp[1]) * (2.0 * s * (1.0 - s))
+        + (cp[3] - cp[2]) * (s * s))
+        * 3.0
+}
+
+// de Casteljau split at s = 0.5
+fn split_bezier(cp: &[Vec3; 4]) -> ([Vec3; 4], [Vec3; 4]) {
+    let cp01 = (cp[0] + cp[1]) * 0.5;
+    let cp12 = (cp[1] + cp[2]) * 0.5;
+    let cp23 = (cp[2] + cp[3]) * 0.5;
+    let cp012 = (cp01 + cp12) * 0.5;
+    let cp123 = (cp12 + cp23) * 0.5;
+    let mid = (cp012 + cp123) * 0.5;
+    ([cp[0], cp01, cp012, mid], [mid, cp123, cp23, cp[3]])
+}
+
+pub struct Curve {
+    pub cp: [Vec3; 4], // control points
+    pub width0: f64,   // width at the root
+    pub width1: f64,   // width at the tip
+    pub mode: CurveMode,
+    pub mat_ptr: Arc<dyn Material>,
+    max_depth: u32,
+}
+impl Hittable for Curve {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // ray space: origin at r.orig, z along the ray, units of world length
+        let ray_length = r.dir.length();
+        let uvw = ONB::build_from_w(&r.dir);
+        let to_ray = |p: Vec3| {
+            let d = p - r.orig;
+            Vec3::new(d * uvw.u, d * uvw.v, d * uvw.w)
+        };
+        let cp = [
+            to_ray(self.cp[0]),
+            to_ray(self.cp[1]),
+            to_ray(self.cp[2]),
+            to_ray(self.cp[3]),
+        ];
+        let (z, s, h) = self.recursive_hit(
+            &cp,
+            0.0,
+            1.0,
+            self.max_depth,
+            t_min * ray_length,
+            t_max * ray_length,
+        )?;
+
+        let t = z / ray_length;
+        let p = r.at(t);
+        let tangent = bezier_derivative(&self.cp, s).unit();
+        let outward_normal = match self.mode {
+            // face the ray, perpendicular to the strand
+            CurveMode::Ribbon => {
+                let facing = -r.dir.unit();
+                let n = facing - tangent * (facing * tangent);
+                if n.squared_length() > 0.0 {
+                    n.unit()
+                } else {
+                    facing
+                }
+            }
+            // from the axis towards the hit point
+            CurveMode::Cylinder => {
+                let n = p - eval_bezier(&self.cp, s);
+                let n = n - tangent * (n * tangent);
+                if n.squared_length() > 0.0 {
+                    n.unit()
+                } else {
+                    -r.dir.unit()
+                }
+            }
+        };
+        let mut ret = HitRecord {
+            t,
+            p,
+            normal: outward_normal,
+            front_face: false,
+            tangent,
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            footprint: Footprint::point(),
+            mat_ptr: self.mat_ptr.clone(),
+            // u runs across the width (0.5 on the axis), v along the strand
+            u: clamp(0.5 * (h + 1.0), 0.0, 1.0),
+            v: s,
+        };
+        ret.set_face_normal(r, &outward_normal);
+        if !ret.mat_ptr.opaque(&ret) {
+            return None;
+        }
+        Some(ret)
+    }
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        let half = 0.5 * self.width0.max(self.width1);
+        let mut min = self.cp[0];
+        let mut max = self.cp[0];
+        for p in self.cp.iter() {
+            min = Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        Some(AABB::new(min - half, max + half))
+    }
+}
+impl Curve {
+    pub fn new(
+        cp: [Vec3; 4],
+        width0: f64,
+        width1: f64,
+        mode: CurveMode,
+        mat_ptr: Arc<dyn Material>,
+    ) -> Self {
+        // split until the control polygon deviates less than a fraction of the width
+        let mut l0: f64 = 0.0;
+        for i in 0..2 {
+            l0 = l0.max((cp[i] - cp[i + 1] * 2.0 + cp[i + 2]).length());
+        }
+        let eps = width0.max(width1) * 0.05;
+        let max_depth = if l0 > 0.0 && eps > 0.0 {
+            let r0 = (std::f64::consts::SQRT_2 * 6.0 * l0 / (8.0 * eps)).log2() / 2.0;
+            clamp(r0.ceil(), 0.0, 10.0) as u32
+        } else {
+            0
+        };
+        Self {
+            cp,
+            width0,
+            width1,
+            mode,
+            mat_ptr,
+            max_depth,
+        }
+    }
+
+    // return: (distance along the ray, curve parameter, offset across the width in [-1, 1])
+    fn recursive_hit(
+        &self,
+        cp: &[Vec3; 4],
+        s0: f64,
+        s1: f64,
+        depth: u32,
+        z_min: f64,
+        z_max: f64,
+    ) -> Option<(f64, f64, f64)> {
+        // reject by the bounds of the control points, grown by the half width
+        let half = 0.5 * lerp(self.width0, self.width1, s0).max(lerp(self.width0, self.width1, s1));
+        let (mut min, mut max) = (cp[0], cp[0]);
+        for p in cp.iter() {
+            min = Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        if min.x - half > 0.0 || max.x + half < 0.0 || min.y - half > 0.0 || max.y + half < 0.0 {
+            return None;
+        }
+        if min.z - half > z_max || max.z + half < z_min {
+            return None;
+        }
+
+        if depth > 0 {
+            let (left, right) = split_bezier(cp);
+            let mid = 0.5 * (s0 + s1);
+            let first = self.recursive_hit(&left, s0, mid, depth - 1, z_min, z_max);
+            let z_max = first.map_or(z_max, |hit| hit.0);
+            let second = self.recursive_hit(&right, mid, s1, depth - 1, z_min, z_max);
+            return second.or(first);
+        }
+
+        match self.mode {
+            CurveMode::Ribbon => self.hit_ribbon(cp, s0, s1, z_min, z_max),
+            CurveMode::Cylinder => self.hit_tube(cp, s0, s1, z_min, z_max),
+        }
+    }
+
+    // the ray (the z axis) against a flat, ray-facing segment
+    fn hit_ribbon(
+        &self,
+        cp: &[Vec3; 4],
+        s0: f64,
+        s1: f64,
+        z_min: f64,
+        z_max: f64,
+    ) -> Option<(f64, f64, f64)> {
+        // closest point of the segment to the ray in the xy plane
+        let seg = cp[3] - cp[0];
+        let seg_xy = seg.x * seg.x + seg.y * seg.y;
+        if seg_xy < 1e-24 {
+            return None;
+        }
+        let w = clamp(-(cp[0].x * seg.x + cp[0].y * seg.y) / seg_xy, 0.0, 1.0);
+        let s = lerp(s0, s1, w);
+        let hit_width = lerp(self.width0, self.width1, s);
+        let pc = eval_bezier(cp, w);
+        let dist2 = pc.x * pc.x + pc.y * pc.y;
+        if dist2 > hit_width * hit_width * 0.25 {
+            return None;
+        }
+        if pc.z < z_min || pc.z > z_max {
+            return None;
+        }
+        // which side of the strand the ray passes on
+        let d = bezier_derivative(cp, w);
+        let edge = d.x * -pc.y + pc.x * d.y;
+        let h = dist2.sqrt() / (0.5 * hit_width);
+        Some((pc.z, s, if edge > 0.0 { h } else { -h }))
+    }
+
+    // the ray (the z axis) against a capsule around the segment
+    fn hit_tube(
+        &self,
+        cp: &[Vec3; 4],
+        s0: f64,
+        s1: f64,
+        z_min: f64,
+        z_max: f64,
+    ) -> Option<(f64, f64, f64)> {
+        let a = cp[0];
+        let ba = cp[3] - a;
+        let radius =
+            0.25 * (lerp(self.width0, self.width1, s0) + lerp(self.width0, self.width1, s1));
+        let rd = Vec3::new(0.0, 0.0, 1.0);
+        let oa = -a;
+
+        let baba = ba * ba;
+        let bard = ba * rd;
+        let baoa = ba * oa;
+        let rdoa = rd * oa;
+        let oaoa = oa * oa;
+        let qa = baba - bard * bard;
+        let qb = baba * rdoa - baoa * bard;
+        let qc = baba * oaoa - baoa * baoa - radius * radius * baba;
+        let disc = qb * qb - qa * qc;
+
+        let mut z = None;
+        if disc >= 0.0 && qa.abs() > 1e-12 {
+            let root = (-qb - disc.sqrt()) / qa;
+            let y = baoa + root * bard;
+            if y > 0.0 && y < baba {
+                z = Some(root);
+            }
+        }
+        if z.is_none() {
+            // the spherical caps keep consecutive pieces joined
+            for cap in [a, cp[3]].iter() {
+                let oc = -*cap;
+                let b = rd * oc;
+                let c = oc * oc - radius * radius;
+                let disc = b * b - c;
+                if disc > 0.0 {
+                    let root = -b - disc.sqrt();
+                    z = match z {
+                        Some(zz) if zz <= root => Some(zz),
+                        _ => Some(root),
+                    };
+                }
+            }
+        }
+        let z = z?;
+        if z < z_min || z > z_max {
+            return None;
+        }
+
+        let w = if baba > 0.0 {
+            clamp((rd * z - a) * ba / baba, 0.0, 1.0)
+        } else {
+            0.0
+        };
+        // offset across the projected width, from the side the ray passes on
+        let axis = a + ba * w;
+        let side = Vec3::new(-ba.y, ba.x, 0.0);
+        let h = if side.squared_length() > 0.0 {
+            clamp(-(axis * side.unit()) / radius, -1.0, 1.0)
+        } else {
+            0.0
+        };
+        Some((z, lerp(s0, s1, w), h))
+    }
+}
+
+// a batch of curves sharing one BVH
+pub struct CurveSet {
+    pub bvh: BVHNode,
+    pub len: usize,
+}
+impl Hittable for CurveSet {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max)
+    }
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.bvh.bounding_box(t0, t1)
+    }
+}
+impl CurveSet {
+    pub fn new(curves: Vec<Curve>) -> Self {
+        let len = curves.len();
+        let mut list = HitTableList::default();
+        for curve in curves {
+            list.add(Arc::new(curve));
+        }
+        Self {
+            bvh: BVHNode::new(&mut list, 0.0, 1.0),
+            len,
+        }
+    }
+}
@@ -0,0 +1,393 @@
+// Hair and fur for curves
+// After Chiang et al., "A Practical and Controllable Hair and Fur Model for Production Path
+// Tracing" (EGSR 2016) and d'Eon et al. (EGSR 2011), as in pbrt-v3: the fiber is a rough
+// dielectric cylinder that reflects (R), goes through (TT), reflects once inside (TRT), and the
+// rest of the paths are lumped into one more lobe. Each lobe is a longitudinal term times an
+// azimuthal one; the cuticle scales tilt the lobes by alpha.
+// The local frame: x along the strand, z facing the viewer, so the offset across the width
+// (u of the curve hit) gives the azimuth the ray hits the fiber at.
+
+use crate::bsdf::*;
+use crate::hittable::HitRecord;
+use crate::material::*;
+use crate::microfacet::*;
+use crate::onb::*;
+use crate::ray::*;
+use crate::shared_tools::*;
+use crate::vec3::Vec3;
+use std::f64::consts::{LN_2, PI};
+
+// R, TT, TRT and the residual
+const P_MAX: usize = 3;
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+fn safe_asin(x: f64) -> f64 {
+    clamp(x, -1.0, 1.0).asin()
+}
+
+// the modified Bessel function of the first kind, order 0
+fn i0(x: f64) -> f64 {
+    let mut val = 0.0;
+    let mut x2i = 1.0;
+    let mut ifact = 1.0;
+    let mut i4 = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            ifact *= i as f64;
+        }
+        val += x2i / (i4 * ifact * ifact);
+        x2i *= x * x;
+        i4 *= 4.0;
+    }
+    val
+}
+
+fn log_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        i0(x).ln()
+    }
+}
+
+// the longitudinal scattering function
+fn mp(cos_theta_i: f64, cos_theta_o: f64, sin_theta_i: f64, sin_theta_o: f64, v: f64) -> f64 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // in logs, sinh(1 / v) overflows
+        (log_i0(a) - b - 1.0 / v + LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        (-b).exp() * i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+// the attenuation of each lobe: Fresnel at the entry and the exits, absorption inside
+fn ap(cos_theta_o: f64, eta: f64, h: f64, t: Vec3) -> [Vec3; P_MAX + 1] {
+    let cos_gamma_o = safe_sqrt(1.0 - h * h);
+    let f = fresnel_dielectric(cos_theta_o * cos_gamma_o, eta);
+    let mut ret = [Vec3::zero(); P_MAX + 1];
+    ret[0] = Vec3::ones() * f;
+    ret[1] = t * ((1.0 - f) * (1.0 - f));
+    for p in 2..P_MAX {
+        ret[p] = ret[p - 1].elemul(t) * f;
+    }
+    // the geometric series of everything after
+    let tf = t * f;
+    ret[P_MAX] = Vec3::new(
+        ret[P_MAX - 1].x * tf.x / (1.0 - tf.x),
+        ret[P_MAX - 1].y * tf.y / (1.0 - tf.y),
+        ret[P_MAX - 1].z * tf.z / (1.0 - tf.z),
+    );
+    ret
+}
+
+// the azimuth lobe p leaves at
+fn phi(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    2.0 * p as f64 * gamma_t - 2.0 * gamma_o + p as f64 * PI
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1.0 + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f64, s: f64) -> f64 {
+    logistic(x, s) / (logistic_cdf(PI, s) - logistic_cdf(-PI, s))
+}
+
+fn sample_trimmed_logistic(u: f64, s: f64) -> f64 {
+    let k = logistic_cdf(PI, s) - logistic_cdf(-PI, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(-PI, s)) - 1.0).ln();
+    clamp(x, -PI, PI)
+}
+
+// the azimuthal scattering function
+fn np(phi_diff: f64, p: usize, s: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    let mut dphi = phi_diff - phi(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+    trimmed_logistic(dphi, s)
+}
+
+fn luminance(c: Vec3) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+// sigma_a: absorption inside the fiber per unit diameter, eta: the IOR of the fiber
+// the lobe shapes come from the roughness given to the constructors
+pub struct Hair {
+    pub sigma_a: Vec3,
+    pub eta: f64,
+    v: [f64; P_MAX + 1],
+    s: f64,
+    sin_2k_alpha: [f64; 3],
+    cos_2k_alpha: [f64; 3],
+}
+impl Material for Hair {
+    // the lobes are wide, light samples pay off
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        let mut bsdf = Bsdf::new(
+            self,
+            ray_in,
+            rec,
+            BsdfFlags::DIFFUSE
+                | BsdfFlags::GLOSSY
+                | BsdfFlags::REFLECTION
+                | BsdfFlags::TRANSMISSION,
+        );
+        // z faces the viewer whatever the curve mode
+        let facing = -ray_in.dir.unit();
+        let n = facing - rec.tangent * (facing * rec.tangent);
+        if rec.tangent.squared_length() > 0.0 && n.squared_length() > 1e-12 {
+            bsdf.frame = ONB::build_from_w_and_tangent(&n, &rec.tangent);
+        }
+        Some(bsdf)
+    }
+    fn sample(&self, bsdf: &Bsdf, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        let h = Self::offset(bsdf.rec);
+        let (sin_theta_o, cos_theta_o) = (wo.x, safe_sqrt(1.0 - wo.x * wo.x));
+        let phi_o = wo.y.atan2(wo.z);
+
+        // pick a lobe by its share of the attenuation, then reuse u[0] for the azimuth
+        let ap_pdf = self.ap_pdf(cos_theta_o, h);
+        let mut u0 = u[0];
+        let mut p = 0;
+        while p < P_MAX && u0 >= ap_pdf[p] {
+            u0 -= ap_pdf[p];
+            p += 1;
+        }
+        let u0 = clamp(u0 / ap_pdf[p].max(1e-12), 0.0, 1.0 - 1e-12);
+
+        // the longitudinal angle around the tilted specular cone
+        let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+        let u1 = u[1].max(1e-5);
+        let cos_theta = 1.0 + self.v[p] * (u1 + (1.0 - u1) * (-2.0 / self.v[p]).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = (2.0 * PI * u[2]).cos();
+        let sin_theta_i = -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+
+        // the azimuth around the lobe's exit
+        let (gamma_o, gamma_t) = self.gammas(cos_theta_o, h);
+        let dphi = if p < P_MAX {
+            phi(p, gamma_o, gamma_t) + sample_trimmed_logistic(u0, self.s)
+        } else {
+            2.0 * PI * u0
+        };
+        let phi_i = phi_o + dphi;
+        let wi = Vec3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        );
+        let pdf = self.pdf(bsdf, wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample::new(
+            wi,
+            self.eval(bsdf, wo, wi),
+            pdf,
+            BsdfFlags::GLOSSY | side(wi),
+        ))
+    }
+    fn eval(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> Vec3 {
+        let h = Self::offset(bsdf.rec);
+        let (sin_theta_o, cos_theta_o) = (wo.x, safe_sqrt(1.0 - wo.x * wo.x));
+        let (sin_theta_i, cos_theta_i) = (wi.x, safe_sqrt(1.0 - wi.x * wi.x));
+        let phi_diff = wi.y.atan2(wi.z) - wo.y.atan2(wo.z);
+
+        // absorption along the refracted path through the fiber
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+        let (gamma_o, gamma_t) = self.gammas(cos_theta_o, h);
+        let attenuation = ap(
+            cos_theta_o,
+            self.eta,
+            h,
+            self.transmittance(gamma_t.cos(), cos_theta_t),
+        );
+
+        // f carries |cos(wi)| with it, the hair model is defined that way already
+        let mut f = Vec3::zero();
+        for (p, ap) in attenuation.iter().enumerate().take(P_MAX) {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            f += *ap
+                * (mp(
+                    cos_theta_i,
+                    cos_theta_op.abs(),
+                    sin_theta_i,
+                    sin_theta_op,
+                    self.v[p],
+                ) * np(phi_diff, p, self.s, gamma_o, gamma_t));
+        }
+        f + attenuation[P_MAX]
+            * (mp(
+                cos_theta_i,
+                cos_theta_o,
+                sin_theta_i,
+                sin_theta_o,
+                self.v[P_MAX],
+            ) / (2.0 * PI))
+    }
+    fn pdf(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> f64 {
+        let h = Self::offset(bsdf.rec);
+        let (sin_theta_o, cos_theta_o) = (wo.x, safe_sqrt(1.0 - wo.x * wo.x));
+        let (sin_theta_i, cos_theta_i) = (wi.x, safe_sqrt(1.0 - wi.x * wi.x));
+        let phi_diff = wi.y.atan2(wi.z) - wo.y.atan2(wo.z);
+        let (gamma_o, gamma_t) = self.gammas(cos_theta_o, h);
+        let ap_pdf = self.ap_pdf(cos_theta_o, h);
+
+        let mut pdf = 0.0;
+        for (p, ap_pdf) in ap_pdf.iter().enumerate().take(P_MAX) {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            pdf += mp(
+                cos_theta_i,
+                cos_theta_op.abs(),
+                sin_theta_i,
+                sin_theta_op,
+                self.v[p],
+            ) * ap_pdf
+                * np(phi_diff, p, self.s, gamma_o, gamma_t);
+        }
+        pdf + mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        ) * ap_pdf[P_MAX]
+            / (2.0 * PI)
+    }
+}
+impl Hair {
+    // beta_m, beta_n: longitudinal and azimuthal roughness in (0, 1]
+    pub fn new(sigma_a: Vec3, beta_m: f64, beta_n: f64) -> Self {
+        Self::new_tilted(sigma_a, beta_m, beta_n, 2.0)
+    }
+    // alpha: the tilt of the cuticle scales in degrees
+    pub fn new_tilted(sigma_a: Vec3, beta_m: f64, beta_n: f64, alpha: f64) -> Self {
+        let beta_m = clamp(beta_m, 1e-3, 1.0);
+        let beta_n = clamp(beta_n, 1e-3, 1.0);
+
+        // the variance of the longitudinal lobes, wider for the later ones
+        let v0 = (0.726 * beta_m + 0.812 * beta_m * beta_m + 3.7 * beta_m.powi(20)).powi(2);
+        let v = [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0];
+        // the scale of the azimuthal logistic
+        let s = (PI / 8.0).sqrt()
+            * (0.265 * beta_n + 1.194 * beta_n * beta_n + 5.372 * beta_n.powi(22));
+        // the tilts of 2^k alpha
+        let mut sin_2k_alpha = [alpha.to_radians().sin(), 0.0, 0.0];
+        let mut cos_2k_alpha = [safe_sqrt(1.0 - sin_2k_alpha[0].powi(2)), 0.0, 0.0];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+        Self {
+            sigma_a,
+            eta: 1.55,
+            v,
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+    // natural hair colors from the pigment concentrations: eumelanin from 0 (blond) to about
+    // 8 (black), pheomelanin adds red
+    pub fn new_melanin(eumelanin: f64, pheomelanin: f64, beta_m: f64, beta_n: f64) -> Self {
+        let eu = Vec3::new(0.419, 0.697, 1.37);
+        let pheo = Vec3::new(0.187, 0.4, 1.05);
+        Self::new(eu * eumelanin + pheo * pheomelanin, beta_m, beta_n)
+    }
+    // the absorption that gives roughly this color after many bounces inside a head of hair
+    pub fn new_from_color(color: Vec3, beta_m: f64, beta_n: f64) -> Self {
+        let b = beta_n;
+        let denom = 5.969 - 0.215 * b + 2.532 * b * b - 10.73 * b.powi(3)
+            + 5.574 * b.powi(4)
+            + 0.245 * b.powi(5);
+        let sigma = |c: f64| (c.max(1e-4).ln() / denom).powi(2);
+        Self::new(
+            Vec3::new(sigma(color.x), sigma(color.y), sigma(color.z)),
+            beta_m,
+            beta_n,
+        )
+    }
+
+    // the offset across the fiber in [-1, 1], see Curve
+    fn offset(rec: &HitRecord) -> f64 {
+        clamp(2.0 * rec.u - 1.0, -1.0 + 1e-6, 1.0 - 1e-6)
+    }
+
+    // the azimuths of the hit and of the refracted ray inside, seen along the fiber
+    fn gammas(&self, cos_theta_o: f64, h: f64) -> (f64, f64) {
+        let sin_theta_o = safe_sqrt(1.0 - cos_theta_o * cos_theta_o);
+        let etap = safe_sqrt(self.eta * self.eta - sin_theta_o * sin_theta_o) / cos_theta_o;
+        (safe_asin(h), safe_asin(h / etap))
+    }
+
+    // one crossing of the fiber
+    fn transmittance(&self, cos_gamma_t: f64, cos_theta_t: f64) -> Vec3 {
+        let d = 2.0 * cos_gamma_t / cos_theta_t;
+        Vec3::new(
+            (-self.sigma_a.x * d).exp(),
+            (-self.sigma_a.y * d).exp(),
+            (-self.sigma_a.z * d).exp(),
+        )
+    }
+
+    // the chance of picking each lobe
+    fn ap_pdf(&self, cos_theta_o: f64, h: f64) -> [f64; P_MAX + 1] {
+        let sin_theta_o = safe_sqrt(1.0 - cos_theta_o * cos_theta_o);
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+        let (_, gamma_t) = self.gammas(cos_theta_o, h);
+        let ap = ap(
+            cos_theta_o,
+            self.eta,
+            h,
+            self.transmittance(gamma_t.cos(), cos_theta_t),
+        );
+        let sum: f64 = ap.iter().map(|a| luminance(*a)).sum();
+        let mut ret = [0.0; P_MAX + 1];
+        for (r, a) in ret.iter_mut().zip(ap.iter()) {
+            *r = if sum > 0.0 {
+                luminance(*a) / sum
+            } else {
+                1.0 / (P_MAX + 1) as f64
+            };
+        }
+        ret
+    }
+
+    // wo rotated by the scales for lobe p: R by -2 alpha, TT by alpha, TRT by 4 alpha
+    fn tilt(&self, p: usize, sin_theta_o: f64, cos_theta_o: f64) -> (f64, f64) {
+        let (sin, cos) = (self.sin_2k_alpha, self.cos_2k_alpha);
+        match p {
+            0 => (
+                sin_theta_o * cos[1] - cos_theta_o * sin[1],
+                cos_theta_o * cos[1] + sin_theta_o * sin[1],
+            ),
+            1 => (
+                sin_theta_o * cos[0] + cos_theta_o * sin[0],
+                cos_theta_o * cos[0] - sin_theta_o * sin[0],
+            ),
+            2 => (
+                sin_theta_o * cos[2] + cos_theta_o * sin[2],
+                cos_theta_o * cos[2] - sin_theta_o * sin[2],
+            ),
+            _ => (sin_theta_o, cos_theta_o),
+        }
+    }
+}
@@ -0,0 +1,649 @@
+// High dynamic range images
+// Radiance .hdr (RGBE: 8-bit mantissas sharing an exponent, run-length encoded by component)
+// and scanline OpenEXR (16- or 32-bit float channels, uncompressed, RLE or zlib), read into
+// linear float RGB without clipping, for ImageTexture.
+
+use std::fs;
+use std::path::Path;
+
+use crate::vec3::Vec3;
+
+fn has_extension(inputpath: &str, ext: &str) -> bool {
+    match Path::new(inputpath).extension().and_then(|e| e.to_str()) {
+        Some(e) => e.eq_ignore_ascii_case(ext),
+        None => false,
+    }
+}
+
+// whether the file is one of the formats read here, by its extension
+pub fn is_hdr_image(inputpath: &str) -> bool {
+    has_extension(inputpath, "hdr") || has_extension(inputpath, "exr")
+}
+
+// return: width, height and the pixels row by row from the top
+pub fn read_hdr_image(inputpath: &str) -> Result<(usize, usize, Vec<Vec3>), String> {
+    let bytes = fs::read(inputpath).map_err(|e| format!("{}: {}", inputpath, e))?;
+    if has_extension(inputpath, "exr") {
+        read_exr(&bytes)
+    } else {
+        read_radiance(&bytes)
+    }
+}
+
+// bytes[start..start + len], or an error past the end of the file
+fn get(bytes: &[u8], start: usize, len: usize) -> Result<&[u8], String> {
+    start
+        .checked_add(len)
+        .and_then(|end| bytes.get(start..end))
+        .ok_or_else(|| "truncated file".to_string())
+}
+
+//*******************
+// Radiance
+
+fn read_radiance(bytes: &[u8]) -> Result<(usize, usize, Vec<Vec3>), String> {
+    let mut pos = 0;
+    let mut next_line = || {
+        if pos >= bytes.len() {
+            return Err("truncated Radiance header".to_string());
+        }
+        let start = pos;
+        while pos < bytes.len() && bytes[pos] != b'\n' {
+            pos += 1;
+        }
+        let line = String::from_utf8_lossy(&bytes[start..pos]).into_owned();
+        pos += 1;
+        Ok(line)
+    };
+    if !next_line()?.starts_with("#?") {
+        return Err("not a Radiance file".to_string());
+    }
+    // the header ends with an empty line
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line.trim() != "FORMAT=32-bit_rle_rgbe" {
+            return Err("only RGBE is supported".to_string());
+        }
+    }
+    // "-Y height +X width": rows from the top, "+Y": from the bottom
+    let resolution = next_line()?;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    if !(fields.len() == 4 && (fields[0] == "-Y" || fields[0] == "+Y") && fields[2] == "+X") {
+        return Err(format!("unsupported Radiance orientation: {}", resolution));
+    }
+    let parse = |field: &str| {
+        field
+            .parse::<usize>()
+            .map_err(|_| format!("bad Radiance resolution: {}", resolution))
+    };
+    let height = parse(fields[1])?;
+    let width = parse(fields[3])?;
+    // a scanline takes at least a byte for 32 pixels, even run-length encoded
+    let pos = pos.min(bytes.len());
+    if (width / 32 + 1).saturating_mul(height) > bytes.len() - pos {
+        return Err("truncated Radiance file".to_string());
+    }
+
+    let mut pos = pos;
+    let mut rows = Vec::with_capacity(height);
+    for _ in 0..height {
+        let mut rgbe = vec![[0u8; 4]; width];
+        let start = get(bytes, pos, 4).unwrap_or(&[0; 4][..]);
+        let rle = (8..=0x7fff).contains(&width)
+            && start[0] == 2
+            && start[1] == 2
+            && ((start[2] as usize) << 8 | start[3] as usize) == width;
+        if rle {
+            pos += 4;
+            // each component on its own: runs (count > 128) and literal spans
+            for component in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = get(bytes, pos, 1)?[0] as usize;
+                    pos += 1;
+                    let n = if count > 128 { count - 128 } else { count };
+                    if n == 0 || x + n > width {
+                        return Err("corrupt Radiance scanline".to_string());
+                    }
+                    if count > 128 {
+                        let b = get(bytes, pos, 1)?[0];
+                        for px in rgbe[x..x + n].iter_mut() {
+                            px[component] = b;
+                        }
+                        pos += 1;
+                    } else {
+                        for (px, b) in rgbe[x..x + n].iter_mut().zip(get(bytes, pos, n)?) {
+                            px[component] = *b;
+                        }
+                        pos += n;
+                    }
+                    x += n;
+                }
+            }
+        } else {
+            for px in rgbe.iter_mut() {
+                px.copy_from_slice(get(bytes, pos, 4)?);
+                pos += 4;
+            }
+        }
+        rows.push(
+            rgbe.iter()
+                .map(|px| {
+                    if px[3] == 0 {
+                        return Vec3::zero();
+                    }
+                    let scale = 2f64.powi(px[3] as i32 - 136);
+                    Vec3::new(px[0] as f64, px[1] as f64, px[2] as f64) * scale
+                })
+                .collect::<Vec<Vec3>>(),
+        );
+    }
+    if fields[0] == "+Y" {
+        rows.reverse();
+    }
+    Ok((width, height, rows.concat()))
+}
+
+//*******************
+// OpenEXR
+
+const EXR_MAGIC: u32 = 20000630;
+
+fn read_u32(bytes: &[u8], pos: usize) -> Result<u32, String> {
+    let b = get(bytes, pos, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_i32(bytes: &[u8], pos: usize) -> Result<i32, String> {
+    Ok(read_u32(bytes, pos)? as i32)
+}
+
+// IEEE 754 half precision
+fn half_to_f64(h: u16) -> f64 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f64;
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 => {
+            if mantissa == 0.0 {
+                f64::INFINITY
+            } else {
+                f64::NAN
+            }
+        }
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+struct ExrChannel {
+    name: String,
+    pixel_type: i32, // 0: u32, 1: half, 2: f32
+}
+impl ExrChannel {
+    fn size(&self) -> usize {
+        if self.pixel_type == 1 {
+            2
+        } else {
+            4
+        }
+    }
+}
+
+// a zero-terminated string at pos, and the position after it
+fn read_name(bytes: &[u8], pos: usize) -> Result<(String, usize), String> {
+    let len = bytes
+        .get(pos..)
+        .and_then(|rest| rest.iter().position(|b| *b == 0))
+        .ok_or_else(|| "truncated OpenEXR header".to_string())?;
+    Ok((
+        String::from_utf8_lossy(&bytes[pos..pos + len]).into_owned(),
+        pos + len + 1,
+    ))
+}
+
+// undo the predictor and the split into two halves the lossless compressors store bytes with
+fn exr_unpredict(mut data: Vec<u8>) -> Vec<u8> {
+    for i in 1..data.len() {
+        data[i] = data[i - 1].wrapping_add(data[i]).wrapping_sub(128);
+    }
+    let half = data.len() - data.len() / 2;
+    let mut ret = Vec::with_capacity(data.len());
+    for i in 0..half {
+        ret.push(data[i]);
+        if half + i < data.len() {
+            ret.push(data[half + i]);
+        }
+    }
+    ret
+}
+
+// OpenEXR's run-length encoding: a negative count n for -n literal bytes, else count + 1
+// copies of the next byte; no more than expected bytes
+fn exr_unrle(packed: &[u8], expected: usize) -> Result<Vec<u8>, String> {
+    let mut data = Vec::with_capacity(expected);
+    let mut p = 0;
+    while p < packed.len() {
+        let count = packed[p] as i8;
+        if count < 0 {
+            let n = (-(count as i32)) as usize;
+            data.extend_from_slice(get(packed, p + 1, n)?);
+            p += 1 + n;
+        } else {
+            let b = get(packed, p + 1, 1)?[0];
+            data.resize(data.len() + count as usize + 1, b);
+            p += 2;
+        }
+        if data.len() > expected {
+            return Err("corrupt OpenEXR chunk".to_string());
+        }
+    }
+    Ok(data)
+}
+
+fn read_exr(bytes: &[u8]) -> Result<(usize, usize, Vec<Vec3>), String> {
+    if read_u32(bytes, 0)? != EXR_MAGIC {
+        return Err("not an OpenEXR file".to_string());
+    }
+    let flags = read_u32(bytes, 4)?;
+    // tiles, deep data, several parts
+    if flags & 0x1a00 != 0 {
+        return Err("only single-part scanline OpenEXR is supported".to_string());
+    }
+
+    let mut channels = Vec::new();
+    let mut compression = 0;
+    let mut window = None;
+    let mut pos = 8;
+    loop {
+        let (name, after) = read_name(bytes, pos)?;
+        if name.is_empty() {
+            pos = after;
+            break;
+        }
+        let (kind, after) = read_name(bytes, after)?;
+        let size = read_i32(bytes, after)?;
+        let value = after + 4;
+        if size < 0 {
+            return Err("corrupt OpenEXR header".to_string());
+        }
+        get(bytes, value, size as usize)?;
+        match (name.as_str(), kind.as_str()) {
+            ("channels", "chlist") => {
+                let mut p = value;
+                while get(bytes, p, 1)?[0] != 0 {
+                    let (name, after) = read_name(bytes, p)?;
+                    let pixel_type = read_i32(bytes, after)?;
+                    if !(0..=2).contains(&pixel_type) {
+                        return Err(format!("unknown OpenEXR pixel type {}", pixel_type));
+                    }
+                    channels.push(ExrChannel { name, pixel_type });
+                    // pixel type, linear, reserved, x and y sampling
+                    p = after + 16;
+                }
+            }
+            ("compression", _) => compression = get(bytes, value, 1)?[0],
+            ("dataWindow", _) => {
+                window = Some((
+                    read_i32(bytes, value)? as i64,
+                    read_i32(bytes, value + 4)? as i64,
+                    read_i32(bytes, value + 8)? as i64,
+                    read_i32(bytes, value + 12)? as i64,
+                ))
+            }
+            _ => {}
+        }
+        pos = value + size as usize;
+    }
+    let window = window.ok_or_else(|| "no dataWindow in the OpenEXR header".to_string())?;
+    if window.2 < window.0 || window.3 < window.1 {
+        return Err("empty OpenEXR data window".to_string());
+    }
+    let width = (window.2 - window.0 + 1) as usize;
+    let height = (window.3 - window.1 + 1) as usize;
+    let lines_per_chunk = match compression {
+        0..=2 => 1,
+        3 => 16,
+        _ => return Err(format!("unsupported OpenEXR compression {}", compression)),
+    };
+    let find = |name: &str| channels.iter().position(|c| c.name == name);
+    // color, or luminance only
+    let rgb = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(r), Some(g), Some(b), _) => [r, g, b],
+        (_, _, _, Some(y)) => [y, y, y],
+        _ => return Err("no RGB or Y channels in the OpenEXR file".to_string()),
+    };
+    let line_size = channels
+        .iter()
+        .map(|c| c.size())
+        .sum::<usize>()
+        .checked_mul(width)
+        .ok_or_else(|| "OpenEXR image too large".to_string())?;
+    let chunks = (height - 1) / lines_per_chunk + 1;
+    // the offset table has to fit, and deflate packs at most about 1000 bytes into one
+    get(bytes, pos, chunks.saturating_mul(8))?;
+    if line_size.saturating_mul(height) / 1024 > bytes.len() {
+        return Err("OpenEXR image larger than its file".to_string());
+    }
+
+    let mut texels = vec![Vec3::zero(); width * height];
+    for chunk in 0..chunks {
+        let entry = get(bytes, pos + 8 * chunk, 8)?;
+        let mut offset = [0u8; 8];
+        offset.copy_from_slice(entry);
+        let offset = u64::from_le_bytes(offset) as usize;
+        let y0 = read_i32(bytes, offset)? as i64 - window.1;
+        if y0 < 0 || y0 as usize >= height {
+            return Err("corrupt OpenEXR chunk".to_string());
+        }
+        let y0 = y0 as usize;
+        let size = read_i32(bytes, offset.saturating_add(4))?;
+        if size < 0 {
+            return Err("corrupt OpenEXR chunk".to_string());
+        }
+        let packed = get(bytes, offset.saturating_add(8), size as usize)?;
+        let lines = lines_per_chunk.min(height - y0);
+        let expected = lines * line_size;
+        // stored raw when compressing would not have made it smaller
+        let data = if packed.len() == expected {
+            packed.to_vec()
+        } else if compression == 1 {
+            exr_unpredict(exr_unrle(packed, expected)?)
+        } else if compression >= 2 {
+            exr_unpredict(
+                miniz_oxide::inflate::decompress_to_vec_zlib(packed)
+                    .map_err(|_| "corrupt OpenEXR chunk".to_string())?,
+            )
+        } else {
+            Vec::new()
+        };
+        if data.len() != expected {
+            return Err("corrupt OpenEXR chunk".to_string());
+        }
+
+        // each line holds every channel in turn, all of a channel's pixels together
+        for line in 0..lines {
+            let mut start = line * line_size;
+            let mut values = vec![Vec::new(); channels.len()];
+            for (channel, v) in channels.iter().zip(values.iter_mut()) {
+                let n = channel.size();
+                *v = (0..width)
+                    .map(|x| {
+                        let p = start + x * n;
+                        let word =
+                            || u32::from_le_bytes([data[p], data[p + 1], data[p + 2], data[p + 3]]);
+                        match channel.pixel_type {
+                            0 => word() as f64,
+                            1 => half_to_f64(u16::from_le_bytes([data[p], data[p + 1]])),
+                            _ => f32::from_bits(word()) as f64,
+                        }
+                    })
+                    .collect::<Vec<f64>>();
+                start += n * width;
+            }
+            let row = (y0 + line) * width;
+            for (x, texel) in texels[row..row + width].iter_mut().enumerate() {
+                *texel = Vec3::new(values[rgb[0]][x], values[rgb[1]][x], values[rgb[2]][x]);
+            }
+        }
+    }
+    Ok((width, height, texels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a few colors RGBE and half floats hold exactly
+    fn color(x: usize, y: usize) -> Vec3 {
+        [
+            Vec3::new(1.0, 0.5, 0.25),
+            Vec3::new(3.0, 3.0, 3.0),
+            Vec3::zero(),
+            Vec3::new(0.5, 6.0, 40.0),
+        ][(x / 3 + y) % 4]
+    }
+
+    fn to_rgbe(c: Vec3) -> [u8; 4] {
+        let m = c.x.max(c.y).max(c.z);
+        if m <= 0.0 {
+            return [0; 4];
+        }
+        let e = m.log2().floor() as i32 + 1;
+        let scale = 2f64.powi(8 - e);
+        [
+            (c.x * scale) as u8,
+            (c.y * scale) as u8,
+            (c.z * scale) as u8,
+            (e + 128) as u8,
+        ]
+    }
+
+    fn radiance(width: usize, height: usize, rle: bool, from_bottom: bool) -> Vec<u8> {
+        let mut bytes = format!(
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{} {} +X {}\n",
+            if from_bottom { "+Y" } else { "-Y" },
+            height,
+            width
+        )
+        .into_bytes();
+        for row in 0..height {
+            let y = if from_bottom { height - 1 - row } else { row };
+            let rgbe: Vec<[u8; 4]> = (0..width).map(|x| to_rgbe(color(x, y))).collect();
+            if !rle {
+                bytes.extend(rgbe.iter().flatten());
+                continue;
+            }
+            bytes.extend(&[2, 2, (width >> 8) as u8, width as u8]);
+            // runs of equal bytes, single bytes as literal spans
+            for component in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let b = rgbe[x][component];
+                    let run = rgbe[x..].iter().take_while(|px| px[component] == b).count();
+                    if run > 1 {
+                        bytes.extend(&[128 + run.min(127) as u8, b]);
+                        x += run.min(127);
+                    } else {
+                        bytes.extend(&[1, b]);
+                        x += 1;
+                    }
+                }
+            }
+        }
+        bytes
+    }
+
+    fn to_half(v: f64) -> u16 {
+        if v == 0.0 {
+            return 0;
+        }
+        let e = v.log2().floor() as i32;
+        let mantissa = ((v / 2f64.powi(e) - 1.0) * 1024.0) as u16;
+        ((e + 15) as u16) << 10 | mantissa
+    }
+
+    // the inverse of exr_unpredict
+    fn exr_predict(data: &[u8]) -> Vec<u8> {
+        let mut ret: Vec<u8> = data.iter().step_by(2).copied().collect();
+        ret.extend(data.iter().skip(1).step_by(2));
+        for i in (1..ret.len()).rev() {
+            ret[i] = ret[i].wrapping_sub(ret[i - 1]).wrapping_add(128);
+        }
+        ret
+    }
+
+    fn exr_rle(data: &[u8]) -> Vec<u8> {
+        let mut ret = Vec::new();
+        let mut p = 0;
+        while p < data.len() {
+            let run = data[p..].iter().take_while(|b| **b == data[p]).count();
+            if run > 2 {
+                let n = run.min(128);
+                ret.extend(&[(n - 1) as u8, data[p]]);
+                p += n;
+            } else {
+                ret.extend(&[-1i8 as u8, data[p]]);
+                p += 1;
+            }
+        }
+        ret
+    }
+
+    fn attribute(bytes: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+        for s in &[name, kind] {
+            bytes.extend(s.as_bytes());
+            bytes.push(0);
+        }
+        bytes.extend(&(value.len() as i32).to_le_bytes());
+        bytes.extend(value);
+    }
+
+    // half float B, G, R, in OpenEXR's alphabetical order
+    fn exr(width: usize, height: usize, compression: u8) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(&EXR_MAGIC.to_le_bytes());
+        bytes.extend(&2u32.to_le_bytes());
+        let mut chlist = Vec::new();
+        for name in &["B", "G", "R"] {
+            chlist.extend(name.as_bytes());
+            chlist.push(0);
+            for v in &[1i32, 0, 1, 1] {
+                chlist.extend(&v.to_le_bytes());
+            }
+        }
+        chlist.push(0);
+        attribute(&mut bytes, "channels", "chlist", &chlist);
+        attribute(&mut bytes, "compression", "compression", &[compression]);
+        let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        attribute(&mut bytes, "dataWindow", "box2i", &window);
+        bytes.push(0);
+
+        let lines_per_chunk = if compression == 3 { 16 } else { 1 };
+        let chunks = (height - 1) / lines_per_chunk + 1;
+        let mut table = bytes.len();
+        bytes.resize(table + 8 * chunks, 0);
+        for chunk in 0..chunks {
+            let y0 = chunk * lines_per_chunk;
+            let mut data = Vec::new();
+            for y in y0..height.min(y0 + lines_per_chunk) {
+                for channel in 0..3 {
+                    for x in 0..width {
+                        let c = color(x, y);
+                        let v = [c.z, c.y, c.x][channel];
+                        data.extend(&to_half(v).to_le_bytes());
+                    }
+                }
+            }
+            let packed = match compression {
+                1 => exr_rle(&exr_predict(&data)),
+                3 => miniz_oxide::deflate::compress_to_vec_zlib(&exr_predict(&data), 6),
+                _ => data,
+            };
+            let offset = bytes.len() as u64;
+            bytes[table..table + 8].copy_from_slice(&offset.to_le_bytes());
+            table += 8;
+            bytes.extend(&(y0 as i32).to_le_bytes());
+            bytes.extend(&(packed.len() as i32).to_le_bytes());
+            bytes.extend(packed);
+        }
+        bytes
+    }
+
+    fn check(image: Result<(usize, usize, Vec<Vec3>), String>, width: usize, height: usize) {
+        let image = image.unwrap();
+        assert_eq!((image.0, image.1), (width, height));
+        for y in 0..height {
+            for x in 0..width {
+                let (got, want) = (image.2[y * width + x], color(x, y));
+                assert!(
+                    (got - want).length() < 1e-12,
+                    "({}, {}): {:?} != {:?}",
+                    x,
+                    y,
+                    got,
+                    want
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(half_to_f64(0x3c00), 1.0);
+        assert_eq!(half_to_f64(0xc000), -2.0);
+        assert_eq!(half_to_f64(0x7bff), 65504.0);
+        assert_eq!(half_to_f64(0x3555), 0.333251953125);
+        // subnormals
+        assert_eq!(half_to_f64(0x0001), 2f64.powi(-24));
+        assert_eq!(half_to_f64(0x03ff), 1023.0 * 2f64.powi(-24));
+        assert_eq!(half_to_f64(0x8000), 0.0);
+        assert_eq!(half_to_f64(0xfc00), f64::NEG_INFINITY);
+        assert!(half_to_f64(0x7e00).is_nan());
+        for v in &[0.125, 0.25, 0.5, 1.0, 3.0, 6.0, 40.0] {
+            assert_eq!(half_to_f64(to_half(*v)), *v);
+        }
+    }
+
+    #[test]
+    fn radiance_round_trip() {
+        check(read_radiance(&radiance(5, 3, false, false)), 5, 3);
+        check(read_radiance(&radiance(5, 3, false, true)), 5, 3);
+        check(read_radiance(&radiance(20, 6, true, false)), 20, 6);
+        check(read_radiance(&radiance(300, 2, true, true)), 300, 2);
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        check(read_exr(&exr(7, 3, 0)), 7, 3);
+        check(read_exr(&exr(24, 5, 1)), 24, 5);
+        // a full chunk of 16 lines and a short one
+        check(read_exr(&exr(24, 21, 3)), 24, 21);
+    }
+
+    #[test]
+    fn broken_files() {
+        // cut off anywhere: an error, no panic
+        let files = [
+            (radiance(5, 3, false, false), false),
+            (radiance(20, 6, true, false), false),
+            (exr(7, 3, 0), true),
+            (exr(24, 5, 1), true),
+            (exr(24, 21, 3), true),
+        ];
+        for (bytes, is_exr) in files.iter() {
+            for len in 0..bytes.len() {
+                let read = if *is_exr { read_exr } else { read_radiance };
+                assert!(
+                    read(&bytes[..len]).is_err(),
+                    "read {} of {} bytes",
+                    len,
+                    bytes.len()
+                );
+            }
+        }
+
+        // a run longer than the scanline
+        let mut bytes = radiance(20, 6, true, false);
+        let header = bytes.windows(2).position(|w| w == [2, 2]).unwrap();
+        bytes[header + 4] = 255;
+        assert!(read_radiance(&bytes).is_err());
+        // a chunk offset past the end of the file
+        let mut bytes = exr(7, 3, 0);
+        let table = bytes.len() - 3 * (8 + 7 * 6) - 3 * 8;
+        bytes[table + 7] = 0x7f;
+        assert!(read_exr(&bytes).is_err());
+    }
+
+    #[test]
+    fn exr_predictor() {
+        let data: Vec<u8> = (0..=255u8).chain(0..7).collect();
+        assert_eq!(exr_unpredict(exr_predict(&data)), data);
+    }
+}
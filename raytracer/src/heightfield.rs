@@ -0,0 +1,389 @@
+// Heightfield terrain: a regular grid of heights over the xz plane, two triangles per cell
+// The ray walks the grid with a 2D DDA, and a min/max mip pyramid lets it skip
+// whole blocks of cells it passes above or below
+
+use image::GenericImageView;
+use std::path::Path;
+
+use crate::bvh::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::Ray;
+use crate::texture::Footprint;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// per-cell min/max heights of one mip level
+struct MinMaxLevel {
+    nx: usize,
+    nz: usize,
+    min: Vec<f64>,
+    max: Vec<f64>,
+}
+
+pub struct Heightfield {
+    pub nx: usize,         // samples along x
+    pub nz: usize,         // samples along z
+    pub heights: Vec<f64>, // world-space heights, row-major in z
+    pub min_corner: Vec3,
+    pub size: Vec3, // extent along x and z, height scale along y
+    pub mat_ptr: Arc<dyn Material>,
+    normals: Vec<Vec3>,
+    mips: Vec<MinMaxLevel>,
+    bbox: AABB,
+}
+impl Hittable for Heightfield {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t0, t1) = self.bbox.hit_range(r, t_min, t_max)?;
+        let top = self.mips.len() - 1;
+        let (ni, nj) = (self.mips[top].nx, self.mips[top].nz);
+        self.traverse(r, top, (0, ni), (0, nj), t0, t1)
+    }
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.bbox.clone())
+    }
+}
+impl Heightfield {
+    // heights are normalized to [0, 1] and scaled by size.y
+    pub fn new(
+        nx: usize,
+        nz: usize,
+        heights: Vec<f64>,
+        min_corner: Vec3,
+        size: Vec3,
+        mat_ptr: Arc<dyn Material>,
+    ) -> Self {
+        assert!(
+            nx >= 2 && nz >= 2,
+            "a heightfield needs at least 2x2 samples"
+        );
+        assert_eq!(heights.len(), nx * nz);
+        let heights: Vec<f64> = heights.iter().map(|h| min_corner.y + h * size.y).collect();
+
+        let mut ret = Self {
+            nx,
+            nz,
+            heights,
+            min_corner,
+            size,
+            mat_ptr,
+            normals: vec![],
+            mips: vec![],
+            bbox: AABB::default(),
+        };
+        ret.build_normals();
+        ret.build_mips();
+        let lowest = ret.mips.last().unwrap().min[0];
+        let highest = ret.mips.last().unwrap().max[0];
+        ret.bbox = AABB::new(
+            Vec3::new(min_corner.x, lowest - 0.0001, min_corner.z),
+            Vec3::new(
+                min_corner.x + size.x,
+                highest + 0.0001,
+                min_corner.z + size.z,
+            ),
+        );
+        ret
+    }
+
+    // use the luminance of a grayscale (or color) image as the height
+    pub fn new_from_image(
+        inputpath: &str,
+        min_corner: Vec3,
+        size: Vec3,
+        mat_ptr: Arc<dyn Material>,
+    ) -> Self {
+        let img = image::open(Path::new(inputpath)).unwrap();
+        let (width, height) = img.dimensions();
+        let mut heights = Vec::with_capacity((width * height) as usize);
+        for j in 0..height {
+            for i in 0..width {
+                let pixel = img.get_pixel(i, j);
+                let luma =
+                    0.2126 * pixel[0] as f64 + 0.7152 * pixel[1] as f64 + 0.0722 * pixel[2] as f64;
+                heights.push(luma / 255.0);
+            }
+        }
+        Self::new(
+            width as usize,
+            height as usize,
+            heights,
+            min_corner,
+            size,
+            mat_ptr,
+        )
+    }
+
+    // sample a procedural height function f(u, v) -> [0, 1], e.g. Perlin::turb
+    pub fn new_from_fn<F: Fn(f64, f64) -> f64>(
+        nx: usize,
+        nz: usize,
+        f: F,
+        min_corner: Vec3,
+        size: Vec3,
+        mat_ptr: Arc<dyn Material>,
+    ) -> Self {
+        let mut heights = Vec::with_capacity(nx * nz);
+        for j in 0..nz {
+            for i in 0..nx {
+                heights.push(f(i as f64 / (nx - 1) as f64, j as f64 / (nz - 1) as f64));
+            }
+        }
+        Self::new(nx, nz, heights, min_corner, size, mat_ptr)
+    }
+
+    fn cell_dx(&self) -> f64 {
+        self.size.x / (self.nx - 1) as f64
+    }
+    fn cell_dz(&self) -> f64 {
+        self.size.z / (self.nz - 1) as f64
+    }
+
+    fn height(&self, i: usize, j: usize) -> f64 {
+        self.heights[j * self.nx + i]
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Vec3 {
+        Vec3::new(
+            self.min_corner.x + i as f64 * self.cell_dx(),
+            self.height(i, j),
+            self.min_corner.z + j as f64 * self.cell_dz(),
+        )
+    }
+
+    // smooth vertex normals from central differences of the heights
+    fn build_normals(&mut self) {
+        let (dx, dz) = (self.cell_dx(), self.cell_dz());
+        let mut normals = Vec::with_capacity(self.nx * self.nz);
+        for j in 0..self.nz {
+            for i in 0..self.nx {
+                let (il, ir) = (i.saturating_sub(1), (i + 1).min(self.nx - 1));
+                let (jl, jr) = (j.saturating_sub(1), (j + 1).min(self.nz - 1));
+                let dhdx = (self.height(ir, j) - self.height(il, j)) / ((ir - il) as f64 * dx);
+                let dhdz = (self.height(i, jr) - self.height(i, jl)) / ((jr - jl) as f64 * dz);
+                normals.push(Vec3::new(-dhdx, 1.0, -dhdz).unit());
+            }
+        }
+        self.normals = normals;
+    }
+
+    fn build_mips(&mut self) {
+        // level 0: one entry per cell, bounding its four corners
+        let (cx, cz) = (self.nx - 1, self.nz - 1);
+        let mut min = Vec::with_capacity(cx * cz);
+        let mut max = Vec::with_capacity(cx * cz);
+        for j in 0..cz {
+            for i in 0..cx {
+                let h = [
+                    self.height(i, j),
+                    self.height(i + 1, j),
+                    self.height(i, j + 1),
+                    self.height(i + 1, j + 1),
+                ];
+                min.push(h.iter().cloned().fold(f64::MAX, f64::min));
+                max.push(h.iter().cloned().fold(f64::MIN, f64::max));
+            }
+        }
+        self.mips = vec![MinMaxLevel {
+            nx: cx,
+            nz: cz,
+            min,
+            max,
+        }];
+
+        // coarser levels merge 2x2 blocks until a single block covers the grid
+        loop {
+            let prev = self.mips.last().unwrap();
+            if prev.nx == 1 && prev.nz == 1 {
+                break;
+            }
+            let (nx, nz) = ((prev.nx + 1) >> 1, (prev.nz + 1) >> 1);
+            let mut min = vec![f64::MAX; nx * nz];
+            let mut max = vec![f64::MIN; nx * nz];
+            for j in 0..prev.nz {
+                for i in 0..prev.nx {
+                    let idx = (j / 2) * nx + i / 2;
+                    min[idx] = min[idx].min(prev.min[j * prev.nx + i]);
+                    max[idx] = max[idx].max(prev.max[j * prev.nx + i]);
+                }
+            }
+            self.mips.push(MinMaxLevel { nx, nz, min, max });
+        }
+    }
+
+    // 2D DDA over the cells of one mip level, restricted to [i0, i1) x [j0, j1) and [t0, t1]
+    // cells the ray's height range overlaps are refined on the next finer level
+    fn traverse(
+        &self,
+        r: &Ray,
+        level: usize,
+        (i0, i1): (usize, usize),
+        (j0, j1): (usize, usize),
+        t0: f64,
+        t1: f64,
+    ) -> Option<HitRecord> {
+        let mip = &self.mips[level];
+        let scale = (1 << level) as f64;
+        // ray in grid units of this level
+        let ox = (r.orig.x - self.min_corner.x) / (self.cell_dx() * scale);
+        let oz = (r.orig.z - self.min_corner.z) / (self.cell_dz() * scale);
+        let dx = r.dir.x / (self.cell_dx() * scale);
+        let dz = r.dir.z / (self.cell_dz() * scale);
+
+        let start_x = ox + dx * t0;
+        let start_z = oz + dz * t0;
+        let mut i = (start_x.floor().max(i0 as f64) as usize).min(i1 - 1);
+        let mut j = (start_z.floor().max(j0 as f64) as usize).min(j1 - 1);
+
+        let (step_i, delta_i, mut next_i) = Self::dda_axis(ox, dx, i);
+        let (step_j, delta_j, mut next_j) = Self::dda_axis(oz, dz, j);
+
+        let mut t = t0;
+        while t < t1 {
+            let t_exit = next_i.min(next_j).min(t1);
+            let idx = j * mip.nx + i;
+            let y0 = r.orig.y + r.dir.y * t;
+            let y1 = r.orig.y + r.dir.y * t_exit;
+            if y0.min(y1) <= mip.max[idx] && y0.max(y1) >= mip.min[idx] {
+                let ret = if level == 0 {
+                    self.hit_cell(r, i, j, t0, t_exit.min(t1))
+                } else {
+                    self.traverse(
+                        r,
+                        level - 1,
+                        (2 * i, (2 * i + 2).min(self.mips[level - 1].nx)),
+                        (2 * j, (2 * j + 2).min(self.mips[level - 1].nz)),
+                        t,
+                        t_exit,
+                    )
+                };
+                if ret.is_some() {
+                    return ret;
+                }
+            }
+
+            // step into the neighbouring cell
+            if next_i < next_j {
+                t = next_i;
+                next_i += delta_i;
+                if step_i > 0 {
+                    i += 1;
+                    if i >= i1 {
+                        break;
+                    }
+                } else {
+                    if i == i0 {
+                        break;
+                    }
+                    i -= 1;
+                }
+            } else {
+                t = next_j;
+                next_j += delta_j;
+                if step_j > 0 {
+                    j += 1;
+                    if j >= j1 {
+                        break;
+                    }
+                } else {
+                    if j == j0 {
+                        break;
+                    }
+                    j -= 1;
+                }
+            }
+        }
+        None
+    }
+
+    // return: step direction, t per cell, t of the first boundary crossing
+    fn dda_axis(o: f64, d: f64, cell: usize) -> (i32, f64, f64) {
+        if d > 0.0 {
+            (1, 1.0 / d, (cell as f64 + 1.0 - o) / d)
+        } else if d < 0.0 {
+            (-1, -1.0 / d, (cell as f64 - o) / d)
+        } else {
+            (0, f64::MAX, f64::MAX)
+        }
+    }
+
+    fn hit_cell(&self, r: &Ray, i: usize, j: usize, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // slightly beyond the cell exit, so hits on the shared edge are not lost
+        let t_max = t_max + 1e-9;
+        let v00 = (i, j);
+        let v10 = (i + 1, j);
+        let v01 = (i, j + 1);
+        let v11 = (i + 1, j + 1);
+        let first = self.hit_triangle(r, v00, v10, v11, t_min, t_max);
+        let t_max = first.as_ref().map_or(t_max, |rec| rec.t);
+        let second = self.hit_triangle(r, v00, v11, v01, t_min, t_max);
+        second.or(first)
+    }
+
+    // Möller–Trumbore intersection, with normals and UVs interpolated from the vertices
+    fn hit_triangle(
+        &self,
+        r: &Ray,
+        a: (usize, usize),
+        b: (usize, usize),
+        c: (usize, usize),
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<HitRecord> {
+        let pa = self.vertex(a.0, a.1);
+        let pb = self.vertex(b.0, b.1);
+        let pc = self.vertex(c.0, c.1);
+        let e1 = pb - pa;
+        let e2 = pc - pa;
+        let pvec = r.dir.cross(e2);
+        let det = e1 * pvec;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.orig - pa;
+        let b1 = (tvec * pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(e1);
+        let b2 = (r.dir * qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = (e2 * qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let b0 = 1.0 - b1 - b2;
+
+        let na = self.normals[a.1 * self.nx + a.0];
+        let nb = self.normals[b.1 * self.nx + b.0];
+        let nc = self.normals[c.1 * self.nx + c.0];
+        let outward_normal = (na * b0 + nb * b1 + nc * b2).unit();
+
+        // the image row j maps back onto v = 1 - j / (nz - 1), like ImageTexture
+        let gx = a.0 as f64 * b0 + b.0 as f64 * b1 + c.0 as f64 * b2;
+        let gz = a.1 as f64 * b0 + b.1 as f64 * b1 + c.1 as f64 * b2;
+        let mut ret = HitRecord {
+            t,
+            p: r.at(t),
+            normal: outward_normal,
+            front_face: false,
+            tangent: Vec3::zero(),
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            footprint: Footprint::point(),
+            mat_ptr: self.mat_ptr.clone(),
+            u: gx / (self.nx - 1) as f64,
+            v: 1.0 - gz / (self.nz - 1) as f64,
+        };
+        ret.set_face_normal(r, &outward_normal);
+        if !ret.mat_ptr.opaque(&ret) {
+            return None;
+        }
+        Some(ret)
+    }
+}
@@ -3,6 +3,7 @@ use crate::material::*;
 use crate::onb::*;
 use crate::ray::Ray;
 use crate::shared_tools::*;
+use crate::texture::Footprint;
 use crate::vec3::Vec3;
 use std::f64::consts::PI;
 use std::sync::Arc;
@@ -18,6 +19,17 @@ pub struct HitRecord {
 
     pub front_face: bool,
 
+    // direction along the strand, only curves fill this in
+    pub tangent: Vec3,
+    // how p changes with u and v, zero where the hittable has no parameterization
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    // how the outward normal changes with u and v, zero on flat surfaces
+    pub dndu: Vec3,
+    pub dndv: Vec3,
+    // what a sample on the image covers here, set from the ray differentials after the hit
+    pub footprint: Footprint,
+
     pub mat_ptr: Arc<dyn Material>,
 }
 impl HitRecord {
@@ -29,6 +41,53 @@ impl HitRecord {
             self.normal = -*outward_normal;
         }
     }
+
+    // where the offset rays of r's differential meet the tangent plane
+    pub fn differential_hits(&self, r: &Ray) -> Option<(Vec3, Vec3)> {
+        let d = r.differential?;
+        let n = self.normal;
+        let plane = |origin: Vec3, dir: Vec3| {
+            let t = (self.p - origin) * n / (dir * n);
+            if t.is_finite() {
+                Some(origin + dir * t)
+            } else {
+                None
+            }
+        };
+        Some((plane(d.rx_origin, d.rx_dir)?, plane(d.ry_origin, d.ry_dir)?))
+    }
+
+    // the footprint of a sample from r's differentials (pbrt's ComputeDifferentials), a
+    // point where r has none; u and v only change where the hittable gives dpdu and dpdv
+    pub fn set_footprint(&mut self, r: &Ray) {
+        self.footprint = Footprint::point();
+        let (px, py) = match self.differential_hits(r) {
+            Some(hits) => hits,
+            None => return,
+        };
+        self.footprint.dpdx = px - self.p;
+        self.footprint.dpdy = py - self.p;
+        // least squares for dp = dpdu du + dpdv dv
+        let (a, b, c) = (
+            self.dpdu * self.dpdu,
+            self.dpdu * self.dpdv,
+            self.dpdv * self.dpdv,
+        );
+        let det = a * c - b * b;
+        if det.abs() < 1e-12 {
+            return;
+        }
+        let solve = |dp: Vec3| {
+            let (x, y) = (self.dpdu * dp, self.dpdv * dp);
+            ((c * x - b * y) / det, (a * y - b * x) / det)
+        };
+        let (dudx, dvdx) = solve(self.footprint.dpdx);
+        let (dudy, dvdy) = solve(self.footprint.dpdy);
+        self.footprint.dudx = dudx;
+        self.footprint.dvdx = dvdx;
+        self.footprint.dudy = dudy;
+        self.footprint.dvdy = dvdy;
+    }
 }
 
 pub trait Hittable: Send + Sync {
@@ -139,18 +198,28 @@ impl Hittable for Sphere {
                 let ret_p = ray.at(root);
                 let outward_normal = (ret_p - self.center) / self.radius;
                 let (u, v) = Sphere::get_uv(outward_normal);
+                let (dpdu, dpdv) = self.get_dpduv(outward_normal);
                 let mut ret = HitRecord {
                     t: root,
                     p: ret_p,
                     normal: outward_normal,
                     front_face: false,
+                    tangent: Vec3::zero(),
+                    dpdu,
+                    dpdv,
+                    dndu: dpdu / self.radius,
+                    dndv: dpdv / self.radius,
+                    footprint: Footprint::point(),
                     mat_ptr: self.mat_ptr.clone(),
 
                     u,
                     v,
                 };
                 ret.set_face_normal(&ray, &outward_normal);
-                return Some(ret);
+                // cut out: the far side may still be there
+                if ret.mat_ptr.opaque(&ret) {
+                    return Some(ret);
+                }
             }
 
             let root = (-half_b + delta_sqrt) / _a;
@@ -158,18 +227,27 @@ impl Hittable for Sphere {
                 let ret_p = ray.at(root);
                 let outward_normal = (ret_p - self.center) / self.radius;
                 let (u, v) = Sphere::get_uv(outward_normal);
+                let (dpdu, dpdv) = self.get_dpduv(outward_normal);
                 let mut ret = HitRecord {
                     t: root,
                     p: ret_p,
                     normal: outward_normal,
                     front_face: false,
+                    tangent: Vec3::zero(),
+                    dpdu,
+                    dpdv,
+                    dndu: dpdu / self.radius,
+                    dndv: dpdv / self.radius,
+                    footprint: Footprint::point(),
                     mat_ptr: self.mat_ptr.clone(),
 
                     u,
                     v,
                 };
                 ret.set_face_normal(&ray, &outward_normal);
-                return Some(ret);
+                if ret.mat_ptr.opaque(&ret) {
+                    return Some(ret);
+                }
             }
         }
         None
@@ -185,8 +263,12 @@ impl Hittable for Sphere {
 
     fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
         if let Some(_rec) = self.hit(&Ray::new(o, v), 0.001, f64::MAX) {
-            let cos_theta_max =
-                (1.0 - self.radius * self.radius / (self.center - o).squared_length()).sqrt();
+            let distance_squared = (self.center - o).squared_length();
+            // from inside (e.g. fog in a glass ball) the sphere covers every direction
+            if distance_squared <= self.radius * self.radius {
+                return 1.0 / (4.0 * PI);
+            }
+            let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
             let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
             1.0 / solid_angle
         } else {
@@ -197,6 +279,9 @@ impl Hittable for Sphere {
     fn random(&self, o: Vec3) -> Vec3 {
         let dir = self.center - o;
         let distance_squared = dir.squared_length();
+        if distance_squared <= self.radius * self.radius {
+            return Vec3::random_unit_vector();
+        }
         let uvw = ONB::build_from_w(&dir);
         uvw.local(&Vec3::rand_to_sphere(self.radius, distance_squared))
     }
@@ -216,6 +301,16 @@ impl Sphere {
         let theta = p.y.asin();
         (1.0 - (phi + PI) / (2.0 * PI), (theta + PI / 2.0) / PI)
     }
+
+    // the derivatives of get_uv's parameterization at the point with normal n
+    fn get_dpduv(&self, n: Vec3) -> (Vec3, Vec3) {
+        let phi = n.z.atan2(n.x);
+        let cos_theta = (n.x * n.x + n.z * n.z).sqrt();
+        (
+            Vec3::new(n.z, 0.0, -n.x) * (2.0 * PI * self.radius),
+            Vec3::new(-n.y * phi.cos(), cos_theta, -n.y * phi.sin()) * (PI * self.radius),
+        )
+    }
 }
 
 #[derive(Clone)]
@@ -245,10 +340,19 @@ impl Hittable for XYRect {
             t,
             normal: outward_normal,
             front_face: false,
+            tangent: Vec3::zero(),
+            dpdu: Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, self.y1 - self.y0, 0.0),
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            footprint: Footprint::point(),
             mat_ptr: self.mat_ptr.clone(),
             p: r.at(t),
         };
         ret.set_face_normal(r, &outward_normal);
+        if !ret.mat_ptr.opaque(&ret) {
+            return None;
+        }
         Some(ret)
     }
     fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
@@ -317,10 +421,19 @@ impl Hittable for XZRect {
             t,
             normal: outward_normal,
             front_face: false,
+            tangent: Vec3::zero(),
+            dpdu: Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, self.z1 - self.z0),
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            footprint: Footprint::point(),
             mat_ptr: self.mat_ptr.clone(),
             p: r.at(t),
         };
         ret.set_face_normal(r, &outward_normal);
+        if !ret.mat_ptr.opaque(&ret) {
+            return None;
+        }
         Some(ret)
     }
     fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
@@ -389,10 +502,19 @@ impl Hittable for YZRect {
             t,
             normal: outward_normal,
             front_face: false,
+            tangent: Vec3::zero(),
+            dpdu: Vec3::new(0.0, self.y1 - self.y0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, self.z1 - self.z0),
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            footprint: Footprint::point(),
             mat_ptr: self.mat_ptr.clone(),
             p: r.at(t),
         };
         ret.set_face_normal(r, &outward_normal);
+        if !ret.mat_ptr.opaque(&ret) {
+            return None;
+        }
         Some(ret)
     }
     fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
@@ -584,6 +706,19 @@ impl Hittable for RotateX {
             normal.y = rec.normal.y * self.cos - rec.normal.z * self.sin;
             normal.z = rec.normal.y * self.sin + rec.normal.z * self.cos;
 
+            // the surface frame turns with it
+            let rotate = |v: Vec3| {
+                Vec3::new(
+                    v.x,
+                    v.y * self.cos - v.z * self.sin,
+                    v.y * self.sin + v.z * self.cos,
+                )
+            };
+            rec.tangent = rotate(rec.tangent);
+            rec.dpdu = rotate(rec.dpdu);
+            rec.dpdv = rotate(rec.dpdv);
+            rec.dndu = rotate(rec.dndu);
+            rec.dndv = rotate(rec.dndv);
             rec.p = p;
             rec.set_face_normal(&rotate_r, &normal);
             Some(rec)
@@ -668,6 +803,19 @@ impl Hittable for RotateY {
             normal.x = rec.normal.x * self.cos + rec.normal.z * self.sin;
             normal.z = rec.normal.x * (-self.sin) + rec.normal.z * self.cos;
 
+            // the surface frame turns with it
+            let rotate = |v: Vec3| {
+                Vec3::new(
+                    v.x * self.cos + v.z * self.sin,
+                    v.y,
+                    -v.x * self.sin + v.z * self.cos,
+                )
+            };
+            rec.tangent = rotate(rec.tangent);
+            rec.dpdu = rotate(rec.dpdu);
+            rec.dpdv = rotate(rec.dpdv);
+            rec.dndu = rotate(rec.dndu);
+            rec.dndv = rotate(rec.dndv);
             rec.p = p;
             rec.set_face_normal(&rotate_r, &normal);
             Some(rec)
@@ -778,6 +926,19 @@ impl Hittable for RotateZ {
             normal.x = rec.normal.x * self.cos - rec.normal.y * self.sin;
             normal.y = rec.normal.x * self.sin + rec.normal.y * self.cos;
 
+            // the surface frame turns with it
+            let rotate = |v: Vec3| {
+                Vec3::new(
+                    v.x * self.cos - v.y * self.sin,
+                    v.x * self.sin + v.y * self.cos,
+                    v.z,
+                )
+            };
+            rec.tangent = rotate(rec.tangent);
+            rec.dpdu = rotate(rec.dpdu);
+            rec.dpdv = rotate(rec.dpdv);
+            rec.dndu = rotate(rec.dndu);
+            rec.dndv = rotate(rec.dndv);
             rec.p = p;
             rec.set_face_normal(&rotate_r, &normal);
             Some(rec)
@@ -868,6 +1029,12 @@ impl Hittable for ConstantMedium {
                     p: r.at(t),
                     normal: Vec3::new(1.0, 0.0, 0.0), // arbitrary
                     front_face: true,                 // arbitrary
+                    tangent: Vec3::zero(),
+                    dpdu: Vec3::zero(),
+                    dpdv: Vec3::zero(),
+                    dndu: Vec3::zero(),
+                    dndv: Vec3::zero(),
+                    footprint: Footprint::point(),
                     mat_ptr: self.phase_func.clone(),
                     u: 0.0,
                     v: 0.0,
@@ -1,17 +1,32 @@
+mod bsdf;
+mod bump;
 mod bvh;
 mod camera;
+mod curve;
+mod hair;
+mod hdr;
+mod heightfield;
 mod hittable;
 mod material;
 mod material_static;
+mod measured;
+mod medium;
+mod microfacet;
 mod onb;
 mod pdf;
+mod phase;
+mod principled;
 mod ray;
 mod scenes;
+mod sdf;
 mod shared_tools;
+mod spectrum;
 mod texture;
+mod thin_film;
 // mod hittable_static;
 #[allow(clippy::float_cmp)]
 mod vec3;
+mod voxel;
 
 use image::{imageops, ImageBuffer, Rgb, RgbImage};
 use indicatif::ProgressBar;
@@ -19,18 +34,35 @@ use std::sync::mpsc::channel;
 use std::sync::Arc;
 use threadpool::ThreadPool;
 
+pub use bsdf::*;
+pub use bump::*;
 pub use bvh::*;
 pub use camera::Camera;
+pub use curve::*;
+pub use hair::*;
+pub use hdr::*;
+pub use heightfield::*;
 pub use hittable::*;
 pub use material::*;
+pub use measured::*;
+pub use medium::*;
+pub use microfacet::*;
 pub use pdf::*;
+pub use phase::*;
+pub use principled::*;
 pub use ray::*;
+pub use sdf::*;
 pub use shared_tools::*;
+pub use spectrum::*;
 pub use texture::*;
+pub use thin_film::*;
 pub use vec3::Vec3;
+pub use voxel::*;
 
 // Image
 const MAX_DEPTH: u32 = 50;
+// steps of a random walk through a scattering medium, they don't count against MAX_DEPTH
+const MAX_WALK: u32 = 1024;
 
 // put pixel onto the image
 #[allow(clippy::eq_op)]
@@ -64,6 +96,108 @@ fn write_color(pixel_x: u32, pixel_y: u32, sample_per_pixel: u32, img: &mut RgbI
     );
 }
 
+// continue the path at a hit: the scattered ray and the throughput weight it carries
+// half light sampling, half BSDF sampling where the BSDF has a lobe that isn't a delta
+fn scatter(r: &Ray, rec: &HitRecord, lights: &HitTableList) -> Option<(Ray, Vec3)> {
+    let bsdf = rec.mat_ptr.bsdf(r, rec)?;
+    let wo = bsdf.to_local(-r.dir.unit());
+    let p_light = if lights.objects.is_empty() || bsdf.flags.is_specular_only() {
+        0.0
+    } else {
+        0.5
+    };
+    let light_pdf = |wi: Vec3| p_light * lights.pdf_value(rec.p, bsdf.to_world(wi));
+
+    let (wi, f, pdf, sharp) = if rand::random::<f64>() < p_light {
+        let wi = bsdf.to_local(lights.random(rec.p).unit());
+        let pdf = light_pdf(wi) + (1.0 - p_light) * bsdf.pdf(wo, wi);
+        (wi, bsdf.eval(wo, wi), pdf, false)
+    } else {
+        let u = [
+            rand::random::<f64>(),
+            rand::random::<f64>(),
+            rand::random::<f64>(),
+        ];
+        let sample = bsdf.sample(wo, u)?;
+        let pdf = if sample.flags.is_specular() {
+            // light sampling never finds a delta lobe
+            (1.0 - p_light) * sample.pdf
+        } else {
+            light_pdf(sample.wi) + (1.0 - p_light) * sample.pdf
+        };
+        (sample.wi, sample.f, pdf, sample.flags.is_sharp())
+    };
+    if pdf <= 0.0 {
+        // sampled where nothing scatters to, e.g. below a glossy surface
+        return None;
+    }
+    let mut scattered = bsdf.spawn(r, wi);
+    if sharp {
+        // mirrors and glass carry the footprint on, widened by their curvature
+        scattered.differential = bsdf.specular_differential(r, wi);
+    }
+    Some((scattered, f / pdf))
+}
+
+// the next surface hit, after the random walk through the scattering medium the ray may be in
+// distances are sampled in one random channel for the whole walk, weighted by the balance
+// heuristic over all three, so colored media need no hero channel
+// return: the segment of the path that reaches it, the hit (None for the background), the
+// path weight on the way and the light the medium gave off along the walk
+fn walk_to_surface(r: &Ray, objects: &HitTableList) -> (Ray, Option<HitRecord>, Vec3, Vec3) {
+    let channel = rand::random::<usize>() % 3;
+    let mut r = r.clone();
+    let mut f = Vec3::ones();
+    let mut pdf = Vec3::ones();
+    let mut emitted = Vec3::zero();
+    let weight = |f: Vec3, pdf: Vec3| {
+        let mean = (pdf * Vec3::ones()) / 3.0;
+        if mean > 0.0 {
+            f / mean
+        } else {
+            Vec3::zero()
+        }
+    };
+    for step in 0..MAX_WALK {
+        // 0.001: get rid of shadow acnes
+        let mut rec = match objects.hit(&r, 0.001, f64::MAX) {
+            Some(rec) => rec,
+            None => return (r, None, weight(f, pdf), emitted),
+        };
+        let (scattered, step_f, step_pdf, step_emitted) = r.free_flight(rec.t, channel);
+        emitted += weight(f.elemul(step_emitted), pdf.elemul(step_pdf));
+        // kept relative to the sampled channel, long walks would underflow
+        let scale = if step_pdf[channel] > 0.0 {
+            step_pdf[channel]
+        } else {
+            1.0
+        };
+        f = f.elemul(step_f) / scale;
+        pdf = pdf.elemul(step_pdf) / scale;
+        r = match scattered {
+            Some(scattered) => scattered,
+            None => {
+                rec.set_footprint(&r);
+                return (r, Some(rec), weight(f, pdf), emitted);
+            }
+        };
+        // absorbed where it scattered
+        if f * Vec3::ones() <= 0.0 {
+            break;
+        }
+        // Russian roulette on long walks
+        if step >= 16 {
+            let w = weight(f, pdf);
+            let q = w.x.max(w.y).max(w.z).min(0.95);
+            if rand::random::<f64>() >= q {
+                break;
+            }
+            f /= q;
+        }
+    }
+    (r, None, Vec3::zero(), emitted)
+}
+
 // get the ray color within the depth
 fn ray_color(
     r: &Ray,
@@ -76,66 +210,23 @@ fn ray_color(
     if depth == 0 {
         return Vec3::zero();
     }
-    let t = objects.hit(r, 0.001, f64::MAX); // 0.001: get rid of shadow acnes
+    // absorbed (or scattered) on the way by the dielectric the ray travels in
+    let (r, t, transmittance, glow) = walk_to_surface(r, objects);
+    let r = &r;
     if let Some(rec) = t {
         let emitted_value = rec.mat_ptr.emitted(r, &rec, rec.u, rec.v, rec.p);
-        let scattered_value = rec.mat_ptr.scatter(r, &rec);
-        if let Some(srec) = scattered_value {
-            // let on_light = Vec3::new(random_f64(213.0, 343.0), 554.0, random_f64(227.0, 332.0));
-            // let to_light = on_light - rec.p;
-            // let distance_squared = to_light.squared_length();
-            // let to_light = to_light.unit();
-            // if to_light * rec.normal < 0.0 {
-            //     return emitted_value;
-            // }
-            // let light_area = ((343 - 213) * (332 - 227)) as f64;
-            // let light_cosine = to_light.y.abs();
-            // // if light_cosine < 0.000001 {
-            // //     return emitted_value;
-            // // }
-
-            // let light_shape = Arc::new(XZRect::new(
-            //     213.0,
-            //     343.0,
-            //     227.0,
-            //     332.0,
-            //     554.0,
-            //     Arc::new(Lambertian::new(Vec3::zero())),
-            // ));
-            if let Some(specular_ray) = srec.specular_ray {
-                return ray_color(&specular_ray, &background, objects, lights, depth - 1)
-                    .elemul(srec.attenuation);
-            }
-            // let p1 = Arc::new(CosinePDF::build_from_w(&rec.normal));
-            if srec.pdf_ptr.is_none() {
-                panic!("pdf_ptr is None!");
-            }
-            let p = if lights.objects.is_empty() {
-                let pdf_ptr = srec.pdf_ptr.unwrap();
-                MixturePDF::new(pdf_ptr.clone(), pdf_ptr)
-            } else {
-                let light_ptr = Arc::new(HittablePDF::new(lights.clone(), rec.p));
-                MixturePDF::new(light_ptr, srec.pdf_ptr.unwrap())
-            };
-
-            // let p = srec.pdf_ptr.unwrap();
-
-            // let p = CosinePDF::build_from_w(&rec.normal);
-            let scattered = Ray::new(rec.p, p.generate());
-            let pdf = p.value(scattered.dir);
-
-            emitted_value
-                + ray_color(&scattered, &background, objects, lights, depth - 1)
-                    .elemul(srec.attenuation)
-                    * rec.mat_ptr.scattering_pdf(r, &rec, &scattered)
-                    / pdf
+        if let Some((scattered, weight)) = scatter(r, &rec, &lights) {
+            (emitted_value
+                + ray_color(&scattered, &background, objects, lights, depth - 1).elemul(weight))
+            .elemul(transmittance)
+                + glow
         } else {
-            emitted_value
+            emitted_value.elemul(transmittance) + glow
         }
     // recurse to add in the child rays
     } else {
         // If the ray hits nothing, return the background color.
-        *background
+        background.elemul(transmittance) + glow
     }
 
     // let unit_dir = r.dir.unit();
@@ -143,6 +234,72 @@ fn ray_color(
     // Vec3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t
 }
 
+// ray_color for the spectral mode: the radiance at each wavelength of the path
+// RGB colors of materials and lights are uplifted to spectra on the fly
+fn ray_spectrum(
+    r: &Ray,
+    lambdas: &[f64; N_LAMBDA],
+    background: &Vec3,
+    objects: &HitTableList,
+    lights: Arc<HitTableList>,
+    uplift: &RgbToSpectrum,
+    depth: u32,
+) -> [f64; N_LAMBDA] {
+    let mut ret = [0.0; N_LAMBDA];
+    if depth == 0 {
+        return ret;
+    }
+    // absorbed (or scattered) on the way by the dielectric the ray travels in
+    let (r, rec, transmittance, glow) = walk_to_surface(r, objects);
+    let r = &r;
+    let rec = match rec {
+        Some(rec) => rec,
+        None => {
+            for (value, lambda) in ret.iter_mut().zip(lambdas.iter()) {
+                *value = uplift.illuminant(*background, *lambda)
+                    * uplift.unbounded(transmittance, *lambda)
+                    + uplift.illuminant(glow, *lambda);
+            }
+            return ret;
+        }
+    };
+
+    let emitted_value = rec.mat_ptr.emitted(r, &rec, rec.u, rec.v, rec.p);
+    for (value, lambda) in ret.iter_mut().zip(lambdas.iter()) {
+        *value = match rec.mat_ptr.emitted_spectrum(r, &rec, *lambda) {
+            Some(emitted) => emitted,
+            None => uplift.illuminant(emitted_value, *lambda),
+        };
+    }
+    if let Some((scattered, weight)) = scatter(r, &rec, &lights) {
+        let incoming = ray_spectrum(
+            &scattered,
+            lambdas,
+            background,
+            objects,
+            lights,
+            uplift,
+            depth - 1,
+        );
+        // dispersion splits the path: only the hero wavelength goes on, for all of them
+        let split = scattered.dispersed && !r.dispersed;
+        for (i, value) in ret.iter_mut().enumerate() {
+            let throughput = match (split, i) {
+                (false, _) => 1.0,
+                (true, 0) => N_LAMBDA as f64,
+                (true, _) => 0.0,
+            };
+            *value += uplift.unbounded(weight, lambdas[i]) * throughput * incoming[i];
+        }
+    }
+
+    for (value, lambda) in ret.iter_mut().zip(lambdas.iter()) {
+        *value =
+            *value * uplift.unbounded(transmittance, *lambda) + uplift.illuminant(glow, *lambda);
+    }
+    ret
+}
+
 #[allow(unused_assignments)]
 fn main() {
     let (tx, rx) = channel();
@@ -166,6 +323,8 @@ fn main() {
     let mut vfov = 20.0;
     let mut dist_to_focus = 10.0;
     let mut aperture = 0.0;
+    // trace wavelengths instead of RGB, see ray_spectrum
+    let mut spectral = false;
     match 7 {
         0 => {
             objects = scenes::former_three_ball_scene();
@@ -252,6 +411,328 @@ fn main() {
             let box_up = Arc::new(Translate::new(box_up, Vec3::new(265.0, 0.0, 295.0)));
             lights.add(box_up);
         }
+        8 => {
+            objects = scenes::sdf_scene();
+            lookfrom = Vec3::new(0.0, 3.0, 12.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 35.0;
+        }
+        9 => {
+            objects = scenes::terrain();
+            lookfrom = Vec3::new(0.0, 6.0, 14.0);
+            lookat = Vec3::new(0.0, 0.0, 0.0);
+            vfov = 40.0;
+        }
+        10 => {
+            objects = scenes::voxel_world();
+            lookfrom = Vec3::new(-6.0, 5.0, 9.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 40.0;
+        }
+        11 => {
+            objects = scenes::fur_and_grass();
+            lookfrom = Vec3::new(0.0, 2.0, 8.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 35.0;
+        }
+        12 => {
+            objects = scenes::clouds();
+            lookfrom = Vec3::new(0.0, 2.0, 10.0);
+            lookat = Vec3::new(0.0, 1.5, 0.0);
+            vfov = 40.0;
+        }
+        13 => {
+            objects = scenes::phase_functions();
+            background = Vec3::zero();
+            lookfrom = Vec3::new(0.0, 2.0, 12.0);
+            lookat = Vec3::new(0.0, 1.2, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XYRect::new(
+                -6.0,
+                6.0,
+                0.5,
+                2.0,
+                -3.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+            lights.add(Arc::new(Sphere::new(
+                Vec3::new(0.0, 6.0, 6.0),
+                1.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        14 => {
+            objects = scenes::fire_and_smoke();
+            background = Vec3::new(0.05, 0.05, 0.08);
+            lookfrom = Vec3::new(0.0, 2.5, 12.0);
+            lookat = Vec3::new(0.0, 1.2, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -2.0,
+                2.0,
+                -1.0,
+                3.0,
+                6.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        15 => {
+            objects = scenes::glass_of_water();
+            background = Vec3::new(0.3, 0.35, 0.4);
+            lookfrom = Vec3::new(0.0, 4.0, 7.0);
+            lookat = Vec3::new(0.0, 1.2, 0.0);
+            vfov = 35.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -3.0,
+                3.0,
+                8.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        16 => {
+            objects = scenes::dispersion();
+            background = Vec3::new(0.05, 0.05, 0.05);
+            lookfrom = Vec3::new(0.0, 3.0, 10.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 35.0;
+            lights.add(Arc::new(Sphere::new(
+                Vec3::new(-6.0, 6.0, -4.0),
+                0.8,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        17 => {
+            objects = scenes::spectral_lights();
+            background = Vec3::zero();
+            lookfrom = Vec3::new(0.0, 3.0, 12.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 35.0;
+            spectral = true;
+            for i in 0..3 {
+                let x = -4.0 + 4.0 * i as f64;
+                lights.add(Arc::new(XZRect::new(
+                    x - 0.8,
+                    x + 0.8,
+                    -0.8,
+                    0.8,
+                    4.0,
+                    Arc::new(Lambertian::new(Vec3::zero())),
+                )));
+            }
+        }
+        18 => {
+            objects = scenes::metals();
+            background = Vec3::new(0.2, 0.25, 0.3);
+            lookfrom = Vec3::new(0.0, 7.0, 11.0);
+            lookat = Vec3::new(0.8, 0.6, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -1.0,
+                1.0,
+                6.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        19 => {
+            objects = scenes::frosted_glass();
+            background = Vec3::new(0.2, 0.25, 0.3);
+            lookfrom = Vec3::new(0.0, 4.0, 11.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -2.0,
+                2.0,
+                -4.0,
+                -2.0,
+                6.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        20 => {
+            objects = scenes::principled();
+            background = Vec3::new(0.2, 0.25, 0.3);
+            lookfrom = Vec3::new(0.0, 7.0, 11.0);
+            lookat = Vec3::new(0.0, 0.6, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -1.0,
+                1.0,
+                6.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        21 => {
+            objects = scenes::layered_materials();
+            background = Vec3::new(0.2, 0.25, 0.3);
+            lookfrom = Vec3::new(0.0, 5.0, 11.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -1.0,
+                1.0,
+                6.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        22 => {
+            objects = scenes::rough_diffuse();
+            background = Vec3::new(0.05, 0.05, 0.08);
+            lookfrom = Vec3::new(0.0, 4.0, 11.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -4.0,
+                -2.0,
+                5.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        23 => {
+            objects = scenes::thin_films();
+            background = Vec3::new(0.3, 0.35, 0.45);
+            lookfrom = Vec3::new(0.0, 4.0, 11.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -4.0,
+                -2.0,
+                5.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        24 => {
+            objects = scenes::subsurface();
+            background = Vec3::new(0.3, 0.35, 0.45);
+            lookfrom = Vec3::new(0.0, 4.0, 11.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -4.0,
+                -2.0,
+                5.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        25 => {
+            objects = scenes::hair();
+            background = Vec3::new(0.3, 0.35, 0.45);
+            lookfrom = Vec3::new(0.0, 4.0, 11.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -4.0,
+                -2.0,
+                5.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        26 => {
+            objects = scenes::measured();
+            background = Vec3::new(0.3, 0.35, 0.45);
+            lookfrom = Vec3::new(0.0, 4.0, 11.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -4.0,
+                -2.0,
+                5.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        27 => {
+            objects = scenes::bump_mapping();
+            background = Vec3::new(0.3, 0.35, 0.45);
+            lookfrom = Vec3::new(0.0, 4.0, 11.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -4.0,
+                -2.0,
+                5.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        28 => {
+            objects = scenes::cutouts();
+            background = Vec3::new(0.3, 0.35, 0.45);
+            lookfrom = Vec3::new(0.0, 4.0, 11.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -4.0,
+                -2.0,
+                5.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        29 => {
+            objects = scenes::texture_mapping();
+            background = Vec3::new(0.3, 0.35, 0.45);
+            lookfrom = Vec3::new(0.0, 4.0, 11.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -4.0,
+                -2.0,
+                5.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        30 => {
+            objects = scenes::texture_filtering();
+            background = Vec3::new(0.6, 0.65, 0.75);
+            lookfrom = Vec3::new(0.0, 1.5, 6.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XZRect::new(
+                -3.0,
+                3.0,
+                -4.0,
+                -2.0,
+                5.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
+        31 => {
+            objects = scenes::hdr_textures();
+            // the sky sphere is the background
+            background = Vec3::zero();
+            lookfrom = Vec3::new(0.0, 4.0, 11.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+            lights.add(Arc::new(XYRect::new(
+                -3.0,
+                3.0,
+                0.5,
+                2.5,
+                -2.0,
+                Arc::new(Lambertian::new(Vec3::zero())),
+            )));
+        }
         _ => {
             // static bvh
             siz = 1080;
@@ -285,9 +766,17 @@ fn main() {
 
     // Camera
     let v_up = Vec3::new(0.0, 1.0, 0.0);
-    let cam = Camera::new(lookfrom, lookat, v_up, vfov, ratio, aperture, dist_to_focus);
+    let mut cam = Camera::new(lookfrom, lookat, v_up, vfov, ratio, aperture, dist_to_focus);
+    cam.set_resolution(image_w, image_h, sample_per_pixel);
     let cam = Arc::new(cam);
 
+    // the RGB to spectrum table for the spectral mode
+    let uplift = if spectral {
+        Some(Arc::new(RgbToSpectrum::new(16)))
+    } else {
+        None
+    };
+
     // Render
 
     for i in 0..n_jobs {
@@ -295,6 +784,7 @@ fn main() {
         let world_ptr = world.clone();
         let cam_ptr = cam.clone();
         let lights_ptr = lights.clone();
+        let uplift_ptr = uplift.clone();
         pool.execute(move || {
             let row_begin = image_h as usize * i / n_jobs;
             let row_end = image_h as usize * (i + 1) / n_jobs;
@@ -311,10 +801,29 @@ fn main() {
                         let u = (i as f64 + rand::random::<f64>()) / (image_w - 1) as f64;
                         let v = (j as f64 + rand::random::<f64>()) / (image_h - 1) as f64;
 
-                        let r = cam_ptr.get_ray(u, v);
+                        let mut r = cam_ptr.get_ray(u, v);
+                        if let Some(uplift) = &uplift_ptr {
+                            // accumulates XYZ, turned into RGB below
+                            let lambdas = sample_wavelengths();
+                            r.wavelength = Some(lambdas[0]);
+                            let values = ray_spectrum(
+                                &r,
+                                &lambdas,
+                                &background,
+                                &world_ptr,
+                                lights_ptr.clone(),
+                                uplift,
+                                MAX_DEPTH,
+                            );
+                            pixel_color += spectrum_to_xyz(&lambdas, &values);
+                            continue;
+                        }
                         pixel_color +=
                             ray_color(&r, &background, &world_ptr, lights_ptr.clone(), MAX_DEPTH);
                     }
+                    if spectral {
+                        pixel_color = xyz_to_srgb(pixel_color);
+                    }
                     write_color(i, img_j, sample_per_pixel, &mut img_tmp, pixel_color);
                 }
             }
@@ -1,32 +1,50 @@
+use crate::bsdf::*;
 use crate::hittable::HitRecord;
-use crate::pdf::*;
+use crate::microfacet::*;
+use crate::onb::*;
+use crate::phase::*;
 use crate::ray::*;
 use crate::shared_tools::*;
+use crate::spectrum::*;
 use crate::texture::*;
+use crate::thin_film::*;
 use crate::vec3::*;
 use std::convert::From;
 use std::f64::consts::PI;
 use std::sync::Arc;
 
-pub struct ScatterRecord {
-    pub specular_ray: Option<Ray>, // TODO: enum
-    pub attenuation: Vec3,
-    pub pdf_ptr: Option<Arc<dyn PDF>>,
-}
-
 // TRAIT Material
 pub trait Material: Send + Sync {
-    // return: color, ray, pdf
-    fn scatter(&self, _ray_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
+    // return: the BSDF at the hit, None where nothing scatters (lights)
+    fn bsdf<'a>(&'a self, _ray_in: &Ray, _rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        None
+    }
+    // return: a direction wi drawn from the lobes, with its f and pdf (see bsdf.rs)
+    fn sample(&self, _bsdf: &Bsdf, _wo: Vec3, _u: [f64; 3]) -> Option<BsdfSample> {
         None
     }
-    fn scattering_pdf(&self, _ray_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+    // return: BSDF * |cos(wi)|, delta lobes left out
+    fn eval(&self, _bsdf: &Bsdf, _wo: Vec3, _wi: Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+    // return: the density sample() picks wi with, delta lobes left out
+    fn pdf(&self, _bsdf: &Bsdf, _wo: Vec3, _wi: Vec3) -> f64 {
         0.0
     }
+    // return: whether a hit at rec stands, false lets the ray go on to whatever is behind
+    // (see Cutout); asked inside the hittables' hit()
+    fn opaque(&self, _rec: &HitRecord) -> bool {
+        true
+    }
     // return: color
     fn emitted(&self, _ray_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: Vec3) -> Vec3 {
         Vec3::zero()
     }
+    // return: the radiance at lambda of lights with a true emission spectrum,
+    // None lets the spectral mode uplift emitted() instead
+    fn emitted_spectrum(&self, _ray_in: &Ray, _rec: &HitRecord, _lambda: f64) -> Option<f64> {
+        None
+    }
 }
 //*******************
 
@@ -35,25 +53,30 @@ pub struct Lambertian {
     pub albedo: Arc<dyn Texture>,
 }
 impl Material for Lambertian {
-    fn scatter(&self, _ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
-        // let uvw = ONB::build_from_w(&rec.normal);
-        // let direction = uvw.local(&Vec3::rand_cosine_direction());
-        // let scatter_dir = rec.normal + Vec3::random_unit_vector(); // Lambertian scattering
-        // let scattered = Ray::new(rec.p, direction.unit());
-        Some(ScatterRecord {
-            specular_ray: None,
-            attenuation: self.albedo.value(rec.u, rec.v, rec.p), // get color value in texture
-            pdf_ptr: Some(Arc::new(CosinePDF::build_from_w(&rec.normal))),
-        })
-    }
-
-    fn scattering_pdf(&self, _ray_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
-        let cos = rec.normal * scattered.dir.unit();
-        if cos < 0.0 {
-            0.0
-        } else {
-            cos / PI
-        }
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        Some(Bsdf::new(
+            self,
+            ray_in,
+            rec,
+            BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION,
+        ))
+    }
+    fn sample(&self, bsdf: &Bsdf, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        let wi = Vec3::cosine_direction(u[1], u[2]);
+        Some(BsdfSample::new(
+            wi,
+            self.eval(bsdf, wo, wi),
+            self.pdf(bsdf, wo, wi),
+            BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION,
+        ))
+    }
+    fn eval(&self, bsdf: &Bsdf, _wo: Vec3, wi: Vec3) -> Vec3 {
+        let rec = bsdf.rec;
+        // get color value in texture
+        self.albedo.filtered(rec.u, rec.v, rec.p, &rec.footprint) * (wi.z.max(0.0) / PI)
+    }
+    fn pdf(&self, _bsdf: &Bsdf, _wo: Vec3, wi: Vec3) -> f64 {
+        wi.z.max(0.0) / PI
     }
 }
 impl Lambertian {
@@ -72,14 +95,171 @@ impl From<Arc<dyn Texture>> for Lambertian {
     }
 }
 
+// Oren–Nayar: diffuse over V-cavities, flatter and brighter towards the light than Lambertian
+// (the qualitative model as in pbrt), sigma: the spread of facet slopes in degrees
+pub struct OrenNayar {
+    pub albedo: Arc<dyn Texture>,
+    pub a: f64,
+    pub b: f64,
+}
+impl Material for OrenNayar {
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        Some(Bsdf::new(
+            self,
+            ray_in,
+            rec,
+            BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION,
+        ))
+    }
+    fn sample(&self, bsdf: &Bsdf, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        let wi = Vec3::cosine_direction(u[1], u[2]);
+        Some(BsdfSample::new(
+            wi,
+            self.eval(bsdf, wo, wi),
+            self.pdf(bsdf, wo, wi),
+            BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION,
+        ))
+    }
+    fn eval(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::zero();
+        }
+        let sin_i = (1.0 - wi.z * wi.z).max(0.0).sqrt();
+        let sin_o = (1.0 - wo.z * wo.z).max(0.0).sqrt();
+        // the cosine of the azimuth between the two
+        let max_cos = if sin_i > 1e-4 && sin_o > 1e-4 {
+            ((wi.x * wo.x + wi.y * wo.y) / (sin_i * sin_o)).max(0.0)
+        } else {
+            0.0
+        };
+        let (sin_alpha, tan_beta) = if wi.z > wo.z {
+            (sin_o, sin_i / wi.z)
+        } else {
+            (sin_i, sin_o / wo.z)
+        };
+        let rec = bsdf.rec;
+        self.albedo.filtered(rec.u, rec.v, rec.p, &rec.footprint)
+            * ((self.a + self.b * max_cos * sin_alpha * tan_beta) * wi.z / PI)
+    }
+    fn pdf(&self, _bsdf: &Bsdf, _wo: Vec3, wi: Vec3) -> f64 {
+        wi.z.max(0.0) / PI
+    }
+}
+impl OrenNayar {
+    pub fn new(albedo: Vec3, sigma: f64) -> Self {
+        Self::new_from_texture(Arc::new(SolidColor::new(albedo)), sigma)
+    }
+    pub fn new_from_texture(albedo: Arc<dyn Texture>, sigma: f64) -> Self {
+        let sigma2 = degree_to_radians(sigma).powi(2);
+        Self {
+            albedo,
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+}
+
+// Cloth: a diffuse base under a sheen lobe that lights up at grazing angles, the "Charlie"
+// distribution of Estevez and Kulla, "Production Friendly Microfacet Sheen BRDF" (2017)
+// with Neubelt and Pettineo's visibility term
+pub struct Fabric {
+    pub albedo: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub alpha: f64,
+}
+impl Material for Fabric {
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        Some(Bsdf::new(
+            self,
+            ray_in,
+            rec,
+            BsdfFlags::DIFFUSE | BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+        ))
+    }
+    // half the samples follow the cosine, half the sheen distribution
+    fn sample(&self, bsdf: &Bsdf, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let wi = if u[0] < 0.5 {
+            Vec3::cosine_direction(u[1], u[2])
+        } else {
+            reflect_local(wo, self.sample_sheen(u[1], u[2]))
+        };
+        Some(BsdfSample::new(
+            wi,
+            self.eval(bsdf, wo, wi),
+            self.pdf(bsdf, wo, wi),
+            BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+        ))
+    }
+    fn eval(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::zero();
+        }
+        let rec = bsdf.rec;
+        let h = (wo + wi).unit();
+        let visibility = 1.0 / (4.0 * (wi.z + wo.z - wi.z * wo.z));
+        (self.albedo.filtered(rec.u, rec.v, rec.p, &rec.footprint) / PI
+            + self.sheen.filtered(rec.u, rec.v, rec.p, &rec.footprint) * (self.d(h) * visibility))
+            * wi.z
+    }
+    fn pdf(&self, _bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).unit();
+        0.5 * wi.z / PI + 0.5 * self.d(h) * h.z / (4.0 * (wo * h).abs().max(1e-6))
+    }
+}
+impl Fabric {
+    // roughness in (0, 1], alpha = roughness^2
+    pub fn new(albedo: Vec3, sheen: Vec3, roughness: f64) -> Self {
+        Self::new_from_texture(
+            Arc::new(SolidColor::new(albedo)),
+            Arc::new(SolidColor::new(sheen)),
+            roughness,
+        )
+    }
+    pub fn new_from_texture(
+        albedo: Arc<dyn Texture>,
+        sheen: Arc<dyn Texture>,
+        roughness: f64,
+    ) -> Self {
+        Self {
+            albedo,
+            sheen,
+            alpha: (roughness * roughness).max(0.07),
+        }
+    }
+    // a dark pile with a bright rim of its own color
+    pub fn velvet(color: Vec3) -> Self {
+        Self::new(color * 0.3, color, 0.5)
+    }
+
+    // the sheen distribution, normalized over the projected area
+    fn d(&self, h: Vec3) -> f64 {
+        let sin_h = (1.0 - h.z * h.z).max(0.0).sqrt();
+        let inv_alpha = 1.0 / self.alpha;
+        (2.0 + inv_alpha) * sin_h.powf(inv_alpha) / (2.0 * PI)
+    }
+    // a microfacet normal distributed as d(h) * h.z
+    fn sample_sheen(&self, u1: f64, u2: f64) -> Vec3 {
+        let sin_h = u1.powf(self.alpha / (2.0 * self.alpha + 1.0));
+        let cos_h = (1.0 - sin_h * sin_h).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        Vec3::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h)
+    }
+}
+
 // lighting thing
 pub struct DiffuseLight {
     pub emit: Arc<dyn Texture>,
     pub intensity: f64,
 }
 impl Material for DiffuseLight {
-    fn emitted(&self, _ray_in: &Ray, _rec: &HitRecord, u: f64, v: f64, p: Vec3) -> Vec3 {
-        self.emit.value(u, v, p) * self.intensity
+    fn emitted(&self, _ray_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Vec3) -> Vec3 {
+        self.emit.filtered(u, v, p, &rec.footprint) * self.intensity
     }
 }
 impl DiffuseLight {
@@ -97,29 +277,53 @@ impl DiffuseLight {
     }
 }
 
+// a light with a physical spectrum: blackbody, D65, A, F-series
+pub struct SpectralLight {
+    pub illuminant: Illuminant,
+    pub intensity: f64,
+    rgb: Vec3, // the illuminant seen by the RGB renderer
+}
+impl Material for SpectralLight {
+    fn emitted(&self, _ray_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: Vec3) -> Vec3 {
+        self.rgb * self.intensity
+    }
+    fn emitted_spectrum(&self, _ray_in: &Ray, _rec: &HitRecord, lambda: f64) -> Option<f64> {
+        Some(self.illuminant.value(lambda) * self.intensity)
+    }
+}
+impl SpectralLight {
+    pub fn new(illuminant: Illuminant, intensity: f64) -> Self {
+        Self {
+            illuminant,
+            intensity,
+            rgb: illuminant.to_srgb(),
+        }
+    }
+}
+
 // Metal Material
 pub struct Metal {
     pub albedo: Vec3,
     pub fuzz: f64, // Fuzzy reflection
 }
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
-        let reflected = Vec3::reflect(ray_in.dir.unit(), rec.normal); // the reflected dir
-        Some(ScatterRecord {
-            specular_ray: Some(Ray::new(
-                rec.p,
-                reflected + Vec3::rand_in_unit_sphere() * self.fuzz,
-            )),
-            attenuation: self.albedo,
-            pdf_ptr: None,
-        })
-        // let scattered = Ray::new(rec.p, reflected + Vec3::rand_in_unit_sphere() * self.fuzz); // the reflected ray
-        // if scattered.dir * rec.normal > 0.0 {
-        //     // whether the reflected ray and the normal are in the same side
-        //     Some((self.albedo, scattered, 0.0))
-        // } else {
-        //     None
-        // }
+    // the fuzz ball has no density to evaluate, so the lobe counts as a delta
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        Some(Bsdf::new(
+            self,
+            ray_in,
+            rec,
+            BsdfFlags::SPECULAR | BsdfFlags::REFLECTION,
+        ))
+    }
+    fn sample(&self, _bsdf: &Bsdf, wo: Vec3, _u: [f64; 3]) -> Option<BsdfSample> {
+        // the reflected dir
+        let wi = reflect_z(wo) + Vec3::rand_in_unit_sphere() * self.fuzz;
+        let mut flags = BsdfFlags::SPECULAR | BsdfFlags::REFLECTION;
+        if self.fuzz > 0.0 {
+            flags = flags | BsdfFlags::GLOSSY;
+        }
+        Some(BsdfSample::new(wi, self.albedo, 1.0, flags))
     }
 }
 impl Metal {
@@ -131,86 +335,763 @@ impl Metal {
     }
 }
 
+// the local frame of a hit, lined up with the tangent where there is one
+pub fn shading_frame(rec: &HitRecord) -> ONB {
+    // x along the strand of a curve, else along u where the hittable has one
+    let tangent = if rec.tangent.squared_length() > 0.0 {
+        rec.tangent
+    } else {
+        rec.dpdu
+    };
+    ONB::build_from_w_and_tangent(&rec.normal, &tangent)
+}
+
+// A metal with GGX microfacets: complex IOR eta + i k per channel, roughness in [0, 1]
+// along the tangent (u) and the bitangent (v), optionally under a thin film (oxide, oil)
+pub struct RoughConductor {
+    pub eta: Vec3,
+    pub k: Vec3,
+    pub roughness_u: f64,
+    pub roughness_v: f64,
+    pub thin_film: Option<ThinFilm>,
+}
+impl Material for RoughConductor {
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        let mut bsdf = Bsdf::new(self, ray_in, rec, BsdfFlags::GLOSSY | BsdfFlags::REFLECTION);
+        // in spectral mode the film is evaluated at the hero wavelength only
+        if self.thin_film.is_some() && bsdf.wavelength.is_some() {
+            bsdf.dispersed = true;
+        }
+        Some(bsdf)
+    }
+    fn sample(&self, bsdf: &Bsdf, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let h = self.ggx().sample_visible(wo, u[1], u[2]);
+        let wi = reflect_local(wo, h);
+        Some(BsdfSample::new(
+            wi,
+            self.eval(bsdf, wo, wi),
+            self.pdf(bsdf, wo, wi),
+            BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+        ))
+    }
+    fn eval(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::zero();
+        }
+        let h = (wo + wi).unit();
+        let ggx = self.ggx();
+        let fresnel = match &self.thin_film {
+            Some(film) => {
+                let rec = bsdf.rec;
+                film.reflectance(
+                    (rec.u, rec.v, rec.p),
+                    wo * h,
+                    1.0,
+                    self.eta,
+                    self.k,
+                    bsdf.wavelength,
+                )
+            }
+            None => fresnel_conductor(wo * h, self.eta, self.k),
+        };
+        fresnel * (ggx.d(h) * ggx.g(wo, wi) / (4.0 * wo.z))
+    }
+    // visible normals, reflected
+    fn pdf(&self, _bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).unit();
+        self.ggx().pdf_visible(wo, h) / (4.0 * (wo * h))
+    }
+}
+impl RoughConductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness: f64) -> Self {
+        Self::new_anisotropic(eta, k, roughness, roughness)
+    }
+    pub fn new_anisotropic(eta: Vec3, k: Vec3, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            eta,
+            k,
+            roughness_u,
+            roughness_v,
+            thin_film: None,
+        }
+    }
+    // e.g. RoughConductor::new_thin_film(RoughConductor::silver(0.1), film) for tarnish
+    pub fn new_thin_film(metal: RoughConductor, film: ThinFilm) -> Self {
+        Self {
+            thin_film: Some(film),
+            ..metal
+        }
+    }
+
+    // measured metals, RGB at about 650nm, 550nm and 450nm
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(0.143, 0.374, 1.442),
+            Vec3::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+    pub fn silver(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(0.155, 0.117, 0.138),
+            Vec3::new(4.828, 3.122, 2.147),
+            roughness,
+        )
+    }
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(0.200, 0.924, 1.102),
+            Vec3::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(1.657, 0.880, 0.521),
+            Vec3::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    pub fn ggx(&self) -> Ggx {
+        Ggx::from_roughness(self.roughness_u, self.roughness_v)
+    }
+}
+
+// Overlapping dielectrics (a glass of water, ice in a drink) are resolved with a priority stack
+// carried by the ray: where volumes overlap the one with the higher priority fills the space,
+// so hits on the lower priority surfaces there are skipped, and each true boundary refracts with
+// the IORs of the media on both sides. Absorption inside is applied along the path by ray_color.
+// A thin film on the surface reflects even where the IORs match: a soap bubble is a film on a
+// dielectric of IOR 1. A scattering inside makes it translucent, see new_subsurface.
 pub struct Dielectric {
     pub ref_idx: f64,
+    pub dispersion: Dispersion,
+    pub absorption: Vec3, // Beer–Lambert coefficients per unit length
+    pub scattering: Vec3, // per unit length, the random walk of Ray::free_flight
+    pub anisotropy: f64,  // Henyey-Greenstein g of the scattering
+    pub priority: u32,
+    pub thin_film: Option<ThinFilm>,
 }
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
-        // None
-        let etai_over_etat = if rec.front_face {
-            1.0 / self.ref_idx
-        } else {
-            self.ref_idx
-        };
-        let unit_dir = ray_in.dir.unit();
-
-        let cos_theta = if (-unit_dir) * rec.normal < 1.0 {
-            (-unit_dir) * rec.normal
-        } else {
-            1.0
-        };
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        let mut bsdf = Bsdf::new(
+            self,
+            ray_in,
+            rec,
+            BsdfFlags::SPECULAR | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION,
+        );
+        // a dispersive surface restricts the path to one wavelength from here on
+        if self.dispersion.is_dispersive() {
+            if bsdf.wavelength.is_none() {
+                let lambda = sample_wavelength();
+                bsdf.wavelength = Some(lambda);
+                bsdf.weight = wavelength_weight(lambda);
+            }
+            bsdf.dispersed = true;
+        }
+        // in spectral mode the film is evaluated at the hero wavelength only
+        if self.thin_film.is_some() && bsdf.wavelength.is_some() {
+            bsdf.dispersed = true;
+        }
+        bsdf.set_boundary(self.nested_medium(), ray_in, self.thin_film.is_some());
+        Some(bsdf)
+    }
+    fn sample(&self, bsdf: &Bsdf, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        let etai_over_etat = 1.0 / bsdf.eta;
+        let cos_theta = wo.z.min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let reflect = BsdfFlags::SPECULAR | BsdfFlags::REFLECTION;
         if etai_over_etat * sin_theta > 1.0 {
             // Total internal reflection
-            Some(ScatterRecord {
-                specular_ray: Some(Ray::new(rec.p, Vec3::reflect(unit_dir, rec.normal))),
-                attenuation: Vec3::ones(),
-                pdf_ptr: None,
-            })
-        } else {
-            let reflect_prob = schlick(cos_theta, etai_over_etat);
-            // proportion: some rays reflect & some refract
-            if rand::random::<f64>() < reflect_prob {
-                // reflect
-                Some(ScatterRecord {
-                    specular_ray: Some(Ray::new(rec.p, Vec3::reflect(unit_dir, rec.normal))),
-                    attenuation: Vec3::ones(),
-                    pdf_ptr: None,
-                })
-            } else {
-                // refract
-                Some(ScatterRecord {
-                    specular_ray: Some(Ray::new(
-                        rec.p,
-                        Vec3::refract(unit_dir, rec.normal, etai_over_etat),
-                    )),
-                    attenuation: Vec3::ones(),
-                    pdf_ptr: None,
-                })
+            return Some(BsdfSample::new(reflect_z(wo), Vec3::ones(), 1.0, reflect));
+        }
+        let reflectance = match &self.thin_film {
+            Some(film) => {
+                let rec = bsdf.rec;
+                film.reflectance(
+                    (rec.u, rec.v, rec.p),
+                    cos_theta,
+                    bsdf.n_i,
+                    Vec3::ones() * (bsdf.n_i * bsdf.eta),
+                    Vec3::zero(),
+                    bsdf.wavelength,
+                )
             }
+            None => Vec3::ones() * schlick(cos_theta, etai_over_etat),
+        };
+        // a colored film reflects each channel differently, pick by the mean
+        let reflect_prob = (reflectance.x + reflectance.y + reflectance.z) / 3.0;
+        // proportion: some rays reflect & some refract
+        if u[0] < reflect_prob {
+            Some(BsdfSample::new(
+                reflect_z(wo),
+                reflectance,
+                reflect_prob,
+                reflect,
+            ))
+        } else {
+            Some(BsdfSample::new(
+                Vec3::refract(-wo, Vec3::new(0.0, 0.0, 1.0), etai_over_etat),
+                Vec3::ones() - reflectance,
+                1.0 - reflect_prob,
+                BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION,
+            ))
         }
     }
 }
 impl Dielectric {
     pub fn new(_ref: f64) -> Self {
-        Self { ref_idx: _ref }
+        Self::new_nested(_ref, Vec3::zero(), 0)
+    }
+    // color: what is left of white light after travelling distance inside
+    pub fn new_tinted(ref_idx: f64, color: Vec3, distance: f64) -> Self {
+        let coef = |c: f64| -c.max(1e-6).ln() / distance;
+        Self::new_nested(
+            ref_idx,
+            Vec3::new(coef(color.x), coef(color.y), coef(color.z)),
+            0,
+        )
+    }
+    pub fn new_nested(ref_idx: f64, absorption: Vec3, priority: u32) -> Self {
+        Self {
+            ref_idx,
+            dispersion: Dispersion::None,
+            absorption,
+            scattering: Vec3::zero(),
+            anisotropy: 0.0,
+            priority,
+            thin_film: None,
+        }
+    }
+    // skin, marble, wax: light walks through the inside until it leaves somewhere else
+    // albedo: the color the surface ends up with, mfp: the mean free path per channel,
+    // anisotropy: of the scattering, in (-1, 1)
+    // the single-scattering albedo is inverted from albedo with the fit of Chiang et al.,
+    // "Practical and Controllable Subsurface Scattering for Production Path Tracing" (2016)
+    pub fn new_subsurface(ref_idx: f64, albedo: Vec3, mfp: Vec3, anisotropy: f64) -> Self {
+        let single = |a: f64| {
+            let a = clamp(a, 0.0, 1.0);
+            1.0 - (4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt()).powi(2)
+        };
+        let sigma_t = |d: f64| 1.0 / d.max(1e-6);
+        let albedo = Vec3::new(single(albedo.x), single(albedo.y), single(albedo.z));
+        let sigma_t = Vec3::new(sigma_t(mfp.x), sigma_t(mfp.y), sigma_t(mfp.z));
+        Self {
+            scattering: albedo.elemul(sigma_t),
+            anisotropy,
+            ..Self::new_nested(ref_idx, (Vec3::ones() - albedo).elemul(sigma_t), 0)
+        }
+    }
+    // e.g. a soap bubble: Dielectric::new_thin_film(1.0, ThinFilm::new(400.0, 1.33))
+    pub fn new_thin_film(ref_idx: f64, film: ThinFilm) -> Self {
+        Self {
+            thin_film: Some(film),
+            ..Self::new(ref_idx)
+        }
     }
+    // e.g. Dispersion::BK7, ref_idx is taken at the sodium D line
+    pub fn new_dispersive(dispersion: Dispersion) -> Self {
+        Self {
+            ref_idx: dispersion.ior(1.5, 589.3),
+            dispersion,
+            absorption: Vec3::zero(),
+            scattering: Vec3::zero(),
+            anisotropy: 0.0,
+            priority: 0,
+            thin_film: None,
+        }
+    }
+
+    fn nested_medium(&self) -> NestedMedium {
+        NestedMedium {
+            id: self as *const Self as usize,
+            priority: self.priority,
+            ref_idx: self.ref_idx,
+            dispersion: self.dispersion,
+            absorption: self.absorption,
+            scattering: self.scattering,
+            anisotropy: self.anisotropy,
+            emission: Vec3::zero(),
+            phase: None,
+        }
+    }
+}
+
+// Frosted glass: GGX microfacet reflection and transmission after Walter et al.,
+// "Microfacet Models for Refraction through Rough Surfaces" (EGSR 2007). Roughness in [0, 1]
+// comes from the first channel of a texture. Takes part in the nested stack like Dielectric.
+pub struct RoughDielectric {
+    pub ref_idx: f64,
+    pub roughness: Arc<dyn Texture>,
+}
+impl Material for RoughDielectric {
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        let mut bsdf = Bsdf::new(
+            self,
+            ray_in,
+            rec,
+            BsdfFlags::GLOSSY | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION,
+        );
+        bsdf.set_boundary(self.nested_medium(), ray_in, false);
+        Some(bsdf)
+    }
+    fn sample(&self, bsdf: &Bsdf, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        let ggx = self.ggx(bsdf.rec);
+        let wi = Self::sample_microfacet(&ggx, wo, bsdf.eta, u)?;
+        Some(BsdfSample::new(
+            wi,
+            Self::eval_microfacet(&ggx, wo, wi, bsdf.eta),
+            Self::pdf_microfacet(&ggx, wo, wi, bsdf.eta),
+            BsdfFlags::GLOSSY | side(wi),
+        ))
+    }
+    fn eval(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> Vec3 {
+        Self::eval_microfacet(&self.ggx(bsdf.rec), wo, wi, bsdf.eta)
+    }
+    fn pdf(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> f64 {
+        Self::pdf_microfacet(&self.ggx(bsdf.rec), wo, wi, bsdf.eta)
+    }
+}
+impl RoughDielectric {
+    pub fn new(ref_idx: f64, roughness: f64) -> Self {
+        Self::new_from_texture(
+            ref_idx,
+            Arc::new(SolidColor::new_from_f64(roughness, 0.0, 0.0)),
+        )
+    }
+    pub fn new_from_texture(ref_idx: f64, roughness: Arc<dyn Texture>) -> Self {
+        Self { ref_idx, roughness }
+    }
+
+    pub fn ggx(&self, rec: &HitRecord) -> Ggx {
+        let r = clamp(
+            self.roughness
+                .filtered(rec.u, rec.v, rec.p, &rec.footprint)
+                .x,
+            0.0,
+            1.0,
+        );
+        Ggx::from_roughness(r, r)
+    }
+
+    // pick the lobe by the Fresnel term of a visible microfacet, u as in Bsdf::sample
+    pub fn sample_microfacet(ggx: &Ggx, wo: Vec3, etap: f64, u: [f64; 3]) -> Option<Vec3> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let h = ggx.sample_visible(wo, u[1], u[2]);
+        if u[0] < fresnel_dielectric(wo * h, etap) {
+            Some(reflect_local(wo, h))
+        } else {
+            refract_local(wo, h, etap)
+        }
+    }
+
+    // the generalized half vector, on the side of wo
+    fn half_vector(wo: Vec3, wi: Vec3, etap: f64) -> Vec3 {
+        let h = if wi.z > 0.0 { wo + wi } else { wo + wi * etap };
+        if h.z < 0.0 {
+            -h.unit()
+        } else {
+            h.unit()
+        }
+    }
+
+    // return: BSDF * |cos(wi)|, wo on the incident side (n_i) with wo.z > 0, etap = n_t / n_i
+    // radiance is not rescaled by etap^2 on refraction, as in Dielectric; it cancels on
+    // closed objects anyway
+    pub fn eval_microfacet(ggx: &Ggx, wo: Vec3, wi: Vec3, etap: f64) -> Vec3 {
+        let h = Self::half_vector(wo, wi, etap);
+        let (cos_o, cos_i) = (wo * h, wi * h);
+        if wo.z <= 0.0 || cos_o <= 0.0 || (cos_i > 0.0) != (wi.z > 0.0) {
+            return Vec3::zero();
+        }
+        let f = fresnel_dielectric(cos_o, etap);
+        let value = if wi.z > 0.0 {
+            f * ggx.d(h) * ggx.g(wo, wi) / (4.0 * wo.z)
+        } else {
+            let denom = cos_o + etap * cos_i;
+            (1.0 - f) * ggx.d(h) * ggx.g(wo, wi) * etap * etap * (cos_i * cos_o).abs()
+                / (wo.z * denom * denom)
+        };
+        Vec3::ones() * value
+    }
+
+    // the density of wi as sampled by sample_microfacet: visible normals times the Jacobian
+    // of the reflection or the refraction mapping, times the lobe probability
+    pub fn pdf_microfacet(ggx: &Ggx, wo: Vec3, wi: Vec3, etap: f64) -> f64 {
+        let h = Self::half_vector(wo, wi, etap);
+        let (cos_o, cos_i) = (wo * h, wi * h);
+        if wo.z <= 0.0 || cos_o <= 0.0 || (cos_i > 0.0) != (wi.z > 0.0) {
+            return 0.0;
+        }
+        let f = fresnel_dielectric(cos_o, etap);
+        if wi.z > 0.0 {
+            f * ggx.pdf_visible(wo, h) / (4.0 * cos_o)
+        } else {
+            let denom = cos_o + etap * cos_i;
+            (1.0 - f) * ggx.pdf_visible(wo, h) * etap * etap * cos_i.abs() / (denom * denom)
+        }
+    }
+
+    fn nested_medium(&self) -> NestedMedium {
+        NestedMedium {
+            id: self as *const Self as usize,
+            priority: 0,
+            ref_idx: self.ref_idx,
+            dispersion: Dispersion::None,
+            absorption: Vec3::zero(),
+            scattering: Vec3::zero(),
+            anisotropy: 0.0,
+            emission: Vec3::zero(),
+            phase: None,
+        }
+    }
+}
+
+// resolve a hit on the boundary of `me` against the nested stack of the ray
+// return: the IORs on both sides, None if the boundary is false there (overlapped by a higher
+// priority medium)
+pub fn boundary_iors(
+    me: &NestedMedium,
+    ray_in: &Ray,
+    wavelength: Option<f64>,
+) -> Option<(f64, f64)> {
+    let ior_of = |m: Option<&NestedMedium>| match m {
+        Some(m) => m.ior(wavelength),
+        None => 1.0,
+    };
+    let my_ior = me.ior(wavelength);
+    let current = ray_in.current_medium();
+    let (n_i, n_t) = if ray_in.media.iter().any(|m| m.id == me.id) {
+        match current {
+            // still inside something stronger: nothing changes here
+            Some(cur) if cur.id != me.id && cur.priority > me.priority => return None,
+            // the far side is whatever is left without me
+            _ => (
+                my_ior,
+                ior_of(top_medium(ray_in.media.iter().filter(|m| m.id != me.id))),
+            ),
+        }
+    } else {
+        match current {
+            Some(cur) if cur.priority > me.priority => return None,
+            _ => (ior_of(current), my_ior),
+        }
+    };
+    Some((n_i, n_t))
+}
+
+// Blends two materials: each hit scatters off b with the probability given by the mask
+// (first channel), off a otherwise, e.g. rust on metal with a NoiseTexture
+pub struct MixMaterial {
+    pub a: Arc<dyn Material>,
+    pub b: Arc<dyn Material>,
+    pub mask: Arc<dyn Texture>,
+}
+impl Material for MixMaterial {
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        if self.picks_b(rec) {
+            self.b.bsdf(ray_in, rec)
+        } else {
+            self.a.bsdf(ray_in, rec)
+        }
+    }
+    // a hit stands if it does on the material that shades it
+    fn opaque(&self, rec: &HitRecord) -> bool {
+        if self.picks_b(rec) {
+            self.b.opaque(rec)
+        } else {
+            self.a.opaque(rec)
+        }
+    }
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Vec3) -> Vec3 {
+        let w = self.weight(rec, &rec.footprint);
+        self.a.emitted(ray_in, rec, u, v, p) * (1.0 - w) + self.b.emitted(ray_in, rec, u, v, p) * w
+    }
+}
+impl MixMaterial {
+    pub fn new(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: f64) -> Self {
+        Self::new_from_texture(a, b, Arc::new(SolidColor::new_from_f64(weight, 0.0, 0.0)))
+    }
+    pub fn new_from_texture(
+        a: Arc<dyn Material>,
+        b: Arc<dyn Material>,
+        mask: Arc<dyn Texture>,
+    ) -> Self {
+        Self { a, b, mask }
+    }
+
+    fn weight(&self, rec: &HitRecord, footprint: &Footprint) -> f64 {
+        clamp(
+            self.mask.filtered(rec.u, rec.v, rec.p, footprint).x,
+            0.0,
+            1.0,
+        )
+    }
+
+    // the choice between a and b at a hit, the same in opaque() and bsdf(): a hash of the hit
+    // in [0, 1) against the mask. Both see the same u, v and t, p may be in an instance's
+    // space; opaque() is asked before the footprint is known, so the mask is taken at the point
+    fn picks_b(&self, rec: &HitRecord) -> bool {
+        let mut h: u64 = 0x9e37_79b9_7f4a_7c15;
+        for x in &[rec.u, rec.v, rec.t] {
+            // splitmix64 over the bits of each coordinate
+            h = (h ^ x.to_bits()).wrapping_add(0x9e37_79b9_7f4a_7c15);
+            h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            h ^= h >> 31;
+        }
+        ((h >> 11) as f64 / (1u64 << 53) as f64) < self.weight(rec, &Footprint::point())
+    }
+}
+
+// A smooth dielectric clear coat over any material, like varnish or car paint. Light
+// reflects off the coat, or refracts in, scatters off the base and refracts out, absorbed
+// on both legs through the layer. Reflection between the coat and the base is left out.
+pub struct CoatedMaterial {
+    pub base: Arc<dyn Material>,
+    pub coating: Coating,
+}
+impl Material for CoatedMaterial {
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        let mut bsdf = self.base.bsdf(ray_in, rec)?;
+        bsdf.flags = bsdf.flags | BsdfFlags::SPECULAR | BsdfFlags::REFLECTION;
+        // one coat per surface: the outer one wins
+        bsdf.coat = Some(self.coating);
+        Some(bsdf)
+    }
+    fn opaque(&self, rec: &HitRecord) -> bool {
+        self.base.opaque(rec)
+    }
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Vec3) -> Vec3 {
+        self.base.emitted(ray_in, rec, u, v, p)
+    }
+}
+impl CoatedMaterial {
+    pub fn new(base: Arc<dyn Material>, ior: f64) -> Self {
+        Self {
+            base,
+            coating: Coating {
+                ior: ior.max(1.0),
+                absorption: Vec3::zero(),
+                thickness: 0.0,
+            },
+        }
+    }
+    // color: what is left of white light after crossing the layer once, straight down
+    pub fn new_tinted(base: Arc<dyn Material>, ior: f64, color: Vec3, thickness: f64) -> Self {
+        let coef = |c: f64| -c.max(1e-6).ln() / thickness;
+        Self {
+            base,
+            coating: Coating {
+                ior: ior.max(1.0),
+                absorption: Vec3::new(coef(color.x), coef(color.y), coef(color.z)),
+                thickness,
+            },
+        }
+    }
+}
+
+// Cuts holes into any material: where the opacity (first channel of a texture, e.g. the alpha
+// of an ImageTexture) is below one half the surface isn't there, a quad with a leaf texture
+// becomes a leaf. Stochastic opacity lets each hit through with the chance 1 - opacity instead,
+// for partly transparent surfaces that still look the same from every side.
+pub struct Cutout {
+    pub base: Arc<dyn Material>,
+    pub opacity: Arc<dyn Texture>,
+    pub stochastic: bool,
+}
+impl Material for Cutout {
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        self.base.bsdf(ray_in, rec)
+    }
+    fn opaque(&self, rec: &HitRecord) -> bool {
+        let alpha = clamp(self.opacity.value(rec.u, rec.v, rec.p).x, 0.0, 1.0);
+        let stands = if self.stochastic {
+            rand::random::<f64>() < alpha
+        } else {
+            alpha >= 0.5
+        };
+        stands && self.base.opaque(rec)
+    }
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Vec3) -> Vec3 {
+        self.base.emitted(ray_in, rec, u, v, p)
+    }
+}
+impl Cutout {
+    pub fn new(base: Arc<dyn Material>, opacity: Arc<dyn Texture>) -> Self {
+        Self {
+            base,
+            opacity,
+            stochastic: false,
+        }
+    }
+    pub fn new_stochastic(base: Arc<dyn Material>, opacity: Arc<dyn Texture>) -> Self {
+        Self {
+            base,
+            opacity,
+            stochastic: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Coating {
+    pub ior: f64,
+    pub absorption: Vec3, // Beer–Lambert coefficients per unit length
+    pub thickness: f64,
 }
+impl Coating {
+    // the direction inside the layer that refracts out as w
+    fn inside(&self, w: Vec3) -> Vec3 {
+        match refract_local(w, Vec3::new(0.0, 0.0, 1.0), self.ior) {
+            Some(t) => -t,
+            None => w,
+        }
+    }
+    // the direction outside that w inside the layer refracts out as, None on total internal
+    // reflection
+    fn outside(&self, w: Vec3) -> Option<Vec3> {
+        refract_local(w, Vec3::new(0.0, 0.0, 1.0), 1.0 / self.ior).map(|t| -t)
+    }
+    // what is left after crossing the layer along w, inside
+    fn transmittance(&self, w: Vec3) -> Vec3 {
+        let d = self.thickness / w.z.abs().max(1e-6);
+        Vec3::new(
+            (-self.absorption.x * d).exp(),
+            (-self.absorption.y * d).exp(),
+            (-self.absorption.z * d).exp(),
+        )
+    }
+
+    // the coat reflects with the Fresnel probability, the base gets the rest
+    pub fn sample(&self, bsdf: &Bsdf, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let f_o = fresnel_dielectric(wo.z, self.ior);
+        if u[0] < f_o {
+            return Some(BsdfSample::new(
+                reflect_z(wo),
+                Vec3::ones() * f_o,
+                f_o,
+                BsdfFlags::SPECULAR | BsdfFlags::REFLECTION,
+            ));
+        }
+        let wo_in = self.inside(wo);
+        let u = [(u[0] - f_o) / (1.0 - f_o), u[1], u[2]];
+        let base = bsdf.material.sample(bsdf, wo_in, u)?;
+        // whatever the base transmits only went through the coat on the way in
+        let wi = if base.wi.z > 0.0 {
+            self.outside(base.wi)?
+        } else {
+            base.wi
+        };
+        if !base.flags.is_specular() {
+            return Some(BsdfSample::new(
+                wi,
+                self.eval(bsdf, wo, wi),
+                self.pdf(bsdf, wo, wi),
+                base.flags,
+            ));
+        }
+        let mut f = base.f.elemul(self.transmittance(wo_in)) * (1.0 - f_o);
+        if base.wi.z > 0.0 {
+            f = f.elemul(self.transmittance(base.wi)) * (1.0 - fresnel_dielectric(wi.z, self.ior));
+        }
+        Some(BsdfSample::new(wi, f, base.pdf * (1.0 - f_o), base.flags))
+    }
+
+    // the base seen through the layer: refracted directions, the Fresnel transmittance both
+    // ways and the change of solid angle on the way out
+    pub fn eval(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z <= 0.0 {
+            return Vec3::zero();
+        }
+        let wo_in = self.inside(wo);
+        let entering = self.transmittance(wo_in) * (1.0 - fresnel_dielectric(wo.z, self.ior));
+        if wi.z <= 0.0 {
+            return bsdf.material.eval(bsdf, wo_in, wi).elemul(entering);
+        }
+        let wi_in = self.inside(wi);
+        let leaving = self.transmittance(wi_in) * (1.0 - fresnel_dielectric(wi.z, self.ior));
+        bsdf.material
+            .eval(bsdf, wo_in, wi_in)
+            .elemul(entering)
+            .elemul(leaving)
+            * (wi.z / (self.ior * self.ior * wi_in.z))
+    }
 
+    pub fn pdf(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        let p_base = 1.0 - fresnel_dielectric(wo.z, self.ior);
+        let wo_in = self.inside(wo);
+        if wi.z <= 0.0 {
+            return p_base * bsdf.material.pdf(bsdf, wo_in, wi);
+        }
+        let wi_in = self.inside(wi);
+        // the Jacobian of the refraction out of the layer
+        p_base * bsdf.material.pdf(bsdf, wo_in, wi_in) * wi.z / (self.ior * self.ior * wi_in.z)
+    }
+}
+
+// the scattering material of a medium; despite the name any phase function can be plugged in
 pub struct Isotropic {
     pub albedo: Arc<dyn Texture>,
+    pub phase: Arc<dyn PhaseFunction>,
 }
 impl Material for Isotropic {
-    fn scatter(&self, _ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
-        Some(ScatterRecord {
-            specular_ray: Some(Ray::new(rec.p, Vec3::rand_in_unit_sphere())),
-            attenuation: self.albedo.value(rec.u, rec.v, rec.p),
-            pdf_ptr: None,
-        })
-        // Some((
-        //     self.albedo.value(rec.u, rec.v, rec.p),
-        //     Ray::new(rec.p, Vec3::rand_in_unit_sphere()),
-        //     0.0,
-        // ))
+    // the phase function takes part in light sampling like any other lobe
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        Some(Bsdf::new(
+            self,
+            ray_in,
+            rec,
+            BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION,
+        ))
+    }
+    fn sample(&self, bsdf: &Bsdf, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        // phase functions follow the light, -wo is the direction the ray travelled in
+        let wi = self.phase.sample(-wo, [u[1], u[2]]);
+        Some(BsdfSample::new(
+            wi,
+            self.eval(bsdf, wo, wi),
+            self.pdf(bsdf, wo, wi),
+            BsdfFlags::DIFFUSE | side(wi),
+        ))
+    }
+    fn eval(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> Vec3 {
+        let rec = bsdf.rec;
+        self.albedo.filtered(rec.u, rec.v, rec.p, &rec.footprint) * self.phase.eval(-wo, wi)
+    }
+    fn pdf(&self, _bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> f64 {
+        self.phase.pdf(-wo, wi)
     }
 }
 impl Isotropic {
     pub fn new_from_color(albedo: Vec3) -> Self {
+        Self::new_from_texture(Arc::new(SolidColor::new(albedo)))
+    }
+    pub fn new_from_texture(albedo: Arc<dyn Texture>) -> Self {
         Self {
-            albedo: Arc::new(SolidColor::new(albedo)),
+            albedo,
+            phase: Arc::new(IsotropicPhase::new()),
         }
     }
-    pub fn new_from_texture(albedo: Arc<dyn Texture>) -> Self {
-        Self { albedo }
+    pub fn new_with_phase(albedo: Vec3, phase: Arc<dyn PhaseFunction>) -> Self {
+        Self {
+            albedo: Arc::new(SolidColor::new(albedo)),
+            phase,
+        }
     }
 }
@@ -0,0 +1,395 @@
+// Measured BRDFs
+// The MERL database (Matusik et al., "A Data-Driven Reflectance Model", SIGGRAPH 2003):
+// isotropic materials measured over the half and difference angles of Rusinkiewicz,
+// 90 x 90 x 180 values per channel, interpolated between the samples here.
+// Directions are sampled from a table of the BRDF times the cosine for each view elevation.
+
+use std::fs;
+
+use crate::bsdf::*;
+use crate::hittable::HitRecord;
+use crate::material::*;
+use crate::ray::*;
+use crate::shared_tools::*;
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+
+const THETA_H: usize = 90;
+const THETA_D: usize = 90;
+const PHI_D: usize = 180;
+const SAMPLES: usize = THETA_H * THETA_D * PHI_D;
+const SCALE: [f64; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+// the sampling tables: view elevations, then cells of (theta_i, phi_i - phi_o)
+const TABLE_THETA_O: usize = 32;
+const TABLE_THETA_I: usize = 32;
+const TABLE_PHI: usize = 64;
+// the share of cosine samples, they cover what the tables miss
+const COSINE_SHARE: f64 = 0.1;
+
+// the samples around a continuous index x: (lower, upper, weight of the upper one)
+fn split(x: f64, n: usize, wrap: bool) -> (usize, usize, f64) {
+    if wrap {
+        let x = x.rem_euclid(n as f64);
+        let i = (x.floor() as usize).min(n - 1);
+        (i, (i + 1) % n, x - i as f64)
+    } else if x <= 0.0 {
+        (0, 0, 0.0)
+    } else if x >= (n - 1) as f64 {
+        (n - 1, n - 1, 0.0)
+    } else {
+        (x.floor() as usize, x.floor() as usize + 1, x - x.floor())
+    }
+}
+
+pub struct MeasuredBrdf {
+    data: Vec<f64>, // red, then green, then blue, as in the file
+    // the cdf over the cells of each table
+    tables: Vec<Vec<f64>>,
+}
+impl Material for MeasuredBrdf {
+    // measured materials range from diffuse to near mirrors; light samples as for Lambertian
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        Some(Bsdf::new(
+            self,
+            ray_in,
+            rec,
+            BsdfFlags::DIFFUSE | BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+        ))
+    }
+    fn sample(&self, bsdf: &Bsdf, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let wi = self.sample_direction(wo, u);
+        let pdf = self.pdf(bsdf, wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample::new(
+            wi,
+            self.eval(bsdf, wo, wi),
+            pdf,
+            BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+        ))
+    }
+    fn eval(&self, _bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::zero();
+        }
+        self.lookup(wo, wi) * wi.z
+    }
+    fn pdf(&self, _bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        self.direction_pdf(wo, wi)
+    }
+}
+impl MeasuredBrdf {
+    // data: 90 x 90 x 180 values per channel, phi_d fastest, then theta_d, then theta_h
+    pub fn new(data: Vec<f64>) -> Self {
+        assert_eq!(data.len(), 3 * SAMPLES);
+        let mut ret = Self {
+            data,
+            tables: Vec::new(),
+        };
+        ret.tables = (0..TABLE_THETA_O).map(|k| ret.build_table(k)).collect();
+        ret
+    }
+
+    // the MERL .binary format, little-endian: i32 x 3 dimensions (90, 90, 180), then
+    // f64 x (3 x 90 x 90 x 180), negative where nothing was measured
+    pub fn new_from_file(inputpath: &str) -> Result<Self, String> {
+        let bytes = fs::read(inputpath).map_err(|e| format!("{}: {}", inputpath, e))?;
+        if bytes.len() < 12 {
+            return Err("not a MERL file".to_string());
+        }
+        let read_i32 = |pos: usize| {
+            i32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+        };
+        if read_i32(0) as usize != THETA_H
+            || read_i32(4) as usize != THETA_D
+            || read_i32(8) as usize != PHI_D
+        {
+            return Err("unexpected MERL dimensions".to_string());
+        }
+        if bytes.len() < 12 + 8 * 3 * SAMPLES {
+            return Err("truncated MERL file".to_string());
+        }
+        let data = bytes[12..12 + 8 * 3 * SAMPLES]
+            .chunks(8)
+            .map(|c| f64::from_le_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]))
+            .collect();
+        Ok(Self::new(data))
+    }
+
+    // f(theta_h, theta_d, phi_d) at the measured angles, e.g. an analytic stand-in for a file
+    pub fn new_from_fn<F: Fn(f64, f64, f64) -> Vec3>(f: F) -> Self {
+        let mut data = vec![0.0; 3 * SAMPLES];
+        for ih in 0..THETA_H {
+            let theta_h = (ih as f64 / THETA_H as f64).powi(2) * 0.5 * PI;
+            for id in 0..THETA_D {
+                let theta_d = id as f64 / THETA_D as f64 * 0.5 * PI;
+                for ip in 0..PHI_D {
+                    let value = f(theta_h, theta_d, ip as f64 / PHI_D as f64 * PI);
+                    for (channel, scale) in SCALE.iter().enumerate() {
+                        data[channel * SAMPLES + (ih * THETA_D + id) * PHI_D + ip] =
+                            value[channel] / scale;
+                    }
+                }
+            }
+        }
+        Self::new(data)
+    }
+
+    // the BRDF between two directions above the surface
+    pub fn lookup(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        // to the half vector and the difference vector around it
+        let h = (wo + wi).unit();
+        let theta_h = h.z.min(1.0).acos();
+        let phi_h = h.y.atan2(h.x);
+        let (sin_ph, cos_ph) = phi_h.sin_cos();
+        let (sin_th, cos_th) = theta_h.sin_cos();
+        let x = wi.x * cos_ph + wi.y * sin_ph;
+        let y = -wi.x * sin_ph + wi.y * cos_ph;
+        let d = Vec3::new(x * cos_th - wi.z * sin_th, y, x * sin_th + wi.z * cos_th);
+        let theta_d = clamp(d.z, -1.0, 1.0).acos();
+        // reciprocity: phi_d and phi_d + pi are the same
+        let phi_d = d.y.atan2(d.x).rem_euclid(PI);
+
+        // the theta_h samples are denser near the highlight
+        let (h0, h1, wh) = split(
+            (theta_h / (0.5 * PI)).sqrt() * THETA_H as f64,
+            THETA_H,
+            false,
+        );
+        let (d0, d1, wd) = split(theta_d / (0.5 * PI) * THETA_D as f64, THETA_D, false);
+        let (p0, p1, wp) = split(phi_d / PI * PHI_D as f64, PHI_D, true);
+
+        let mut ret = [0.0; 3];
+        for (channel, value) in ret.iter_mut().enumerate() {
+            let at = |ih: usize, id: usize, ip: usize| {
+                self.data[channel * SAMPLES + (ih * THETA_D + id) * PHI_D + ip].max(0.0)
+            };
+            let lerp_p = |ih: usize, id: usize| at(ih, id, p0) * (1.0 - wp) + at(ih, id, p1) * wp;
+            let lerp_d = |ih: usize| lerp_p(ih, d0) * (1.0 - wd) + lerp_p(ih, d1) * wd;
+            *value = (lerp_d(h0) * (1.0 - wh) + lerp_d(h1) * wh) * SCALE[channel];
+        }
+        Vec3::new(ret[0], ret[1], ret[2])
+    }
+
+    // a direction above the surface, from the table of wo's elevation or the cosine
+    fn sample_direction(&self, wo: Vec3, u: [f64; 3]) -> Vec3 {
+        if u[0] < COSINE_SHARE {
+            Vec3::cosine_direction(u[1], u[2])
+        } else {
+            let cdf = &self.tables[Self::table_index(wo)];
+            // the first cell past u[1]
+            let (mut lo, mut hi) = (0, cdf.len() - 1);
+            while lo < hi {
+                let mid = (lo + hi) / 2;
+                if u[1] < cdf[mid] {
+                    hi = mid;
+                } else {
+                    lo = mid + 1;
+                }
+            }
+            let cell = lo;
+            let below = if cell > 0 { cdf[cell - 1] } else { 0.0 };
+            let t = ((u[1] - below) / (cdf[cell] - below).max(1e-12)).min(1.0);
+            let theta = (((cell / TABLE_PHI) as f64 + t) / TABLE_THETA_I as f64) * 0.5 * PI;
+            let phi = (((cell % TABLE_PHI) as f64 + u[2]) / TABLE_PHI as f64) * 2.0 * PI
+                + wo.y.atan2(wo.x);
+            Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            )
+        }
+    }
+    fn direction_pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let cdf = &self.tables[Self::table_index(wo)];
+        let theta = wi.z.min(1.0).acos();
+        let phi = (wi.y.atan2(wi.x) - wo.y.atan2(wo.x)).rem_euclid(2.0 * PI);
+        let i = ((theta / (0.5 * PI) * TABLE_THETA_I as f64) as usize).min(TABLE_THETA_I - 1);
+        let j = ((phi / (2.0 * PI) * TABLE_PHI as f64) as usize).min(TABLE_PHI - 1);
+        let cell = i * TABLE_PHI + j;
+        let p = cdf[cell] - if cell > 0 { cdf[cell - 1] } else { 0.0 };
+        // from the cell to solid angle
+        let area = (0.5 * PI / TABLE_THETA_I as f64) * (2.0 * PI / TABLE_PHI as f64);
+        let sin_theta = theta.sin().max(1e-6);
+        COSINE_SHARE * wi.z / PI + (1.0 - COSINE_SHARE) * p / (area * sin_theta)
+    }
+
+    fn table_index(wo: Vec3) -> usize {
+        let theta_o = wo.z.min(1.0).acos();
+        ((theta_o / (0.5 * PI) * TABLE_THETA_O as f64) as usize).min(TABLE_THETA_O - 1)
+    }
+
+    // the cdf of the luminance of f cos sin over the cells, seen from the middle of the
+    // elevation band k
+    fn build_table(&self, k: usize) -> Vec<f64> {
+        let theta_o = (k as f64 + 0.5) / TABLE_THETA_O as f64 * 0.5 * PI;
+        let wo = Vec3::new(theta_o.sin(), 0.0, theta_o.cos());
+        let mut cdf = Vec::with_capacity(TABLE_THETA_I * TABLE_PHI);
+        let mut sum = 0.0;
+        for i in 0..TABLE_THETA_I {
+            let theta = (i as f64 + 0.5) / TABLE_THETA_I as f64 * 0.5 * PI;
+            for j in 0..TABLE_PHI {
+                let phi = (j as f64 + 0.5) / TABLE_PHI as f64 * 2.0 * PI;
+                let wi = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                let f = self.lookup(wo, wi);
+                sum += (0.2126 * f.x + 0.7152 * f.y + 0.0722 * f.z) * theta.cos() * theta.sin();
+                cdf.push(sum);
+            }
+        }
+        let n = cdf.len() as f64;
+        for (idx, c) in cdf.iter_mut().enumerate() {
+            // nothing measured: uniform over the cells
+            *c = if sum > 0.0 {
+                *c / sum
+            } else {
+                (idx + 1) as f64 / n
+            };
+        }
+        cdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn direction(theta: f64, phi: f64) -> Vec3 {
+        Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        )
+    }
+
+    #[test]
+    fn bad_files() {
+        let path = std::env::temp_dir().join("raytracer_truncated.binary");
+        let mut bytes = Vec::new();
+        for n in &[90i32, 90, 180] {
+            bytes.extend(&n.to_le_bytes());
+        }
+        bytes.extend(&[0u8; 800]);
+        fs::write(&path, &bytes).unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            MeasuredBrdf::new_from_file(path).err(),
+            Some("truncated MERL file".to_string())
+        );
+        bytes[0] = 91;
+        fs::write(path, &bytes).unwrap();
+        assert_eq!(
+            MeasuredBrdf::new_from_file(path).err(),
+            Some("unexpected MERL dimensions".to_string())
+        );
+        fs::remove_file(path).unwrap();
+        assert!(MeasuredBrdf::new_from_file(path).is_err());
+    }
+
+    #[test]
+    fn half_difference_angles() {
+        // the angles themselves, theta_h by its sample index, which lookup interpolates linearly
+        let brdf = MeasuredBrdf::new_from_fn(|theta_h, theta_d, phi_d| {
+            Vec3::new((theta_h / (0.5 * PI)).sqrt(), theta_d, phi_d)
+        });
+        let (theta_h, phi_h, theta_d, phi_d) = (0.5, 0.8, 0.35, 1.0);
+        // the difference vector turned back around the half vector
+        let h = direction(theta_h, phi_h);
+        let d = direction(theta_d, phi_d);
+        let (sin_th, cos_th) = theta_h.sin_cos();
+        let (sin_ph, cos_ph) = phi_h.sin_cos();
+        let x = d.x * cos_th + d.z * sin_th;
+        let z = -d.x * sin_th + d.z * cos_th;
+        let wi = Vec3::new(x * cos_ph - d.y * sin_ph, x * sin_ph + d.y * cos_ph, z);
+        let wo = h * (2.0 * (wi * h)) - wi;
+        let want = Vec3::new((theta_h / (0.5 * PI)).sqrt(), theta_d, phi_d);
+        for got in &[brdf.lookup(wo, wi), brdf.lookup(wi, wo)] {
+            assert!((*got - want).length() < 1e-9, "{:?} != {:?}", got, want);
+        }
+
+        // mirrored about the normal: no half angle, the difference angle is the incidence
+        let got = brdf.lookup(direction(0.6, 0.0), direction(0.6, PI));
+        assert!(
+            got.x.abs() < 1e-9 && (got.y - 0.6).abs() < 1e-9,
+            "{:?}",
+            got
+        );
+    }
+
+    #[test]
+    fn sample_matches_pdf() {
+        // a diffuse base and a narrow highlight, so that the tables matter
+        let brdf = MeasuredBrdf::new_from_fn(|theta_h, _, _| {
+            Vec3::new(0.1, 0.1, 0.1) + Vec3::ones() * (-(theta_h / 0.1).powi(2)).exp() * 50.0
+        });
+        // a Halton sequence: every table cell gets its share of the samples
+        let radical_inverse = |mut k: usize, base: usize| {
+            let (mut ret, mut digit) = (0.0, 1.0 / base as f64);
+            while k > 0 {
+                ret += (k % base) as f64 * digit;
+                k /= base;
+                digit /= base as f64;
+            }
+            ret
+        };
+        // the grid lines up with the table cells
+        let n = 256;
+        for theta_o in &[0.2, 0.7, 1.3] {
+            let wo = direction(*theta_o, 0.4);
+            // the projected solid angle of the hemisphere and the solid angle of the cone
+            // within 45 degrees from the samples, the probability of the cone from them and
+            // from the pdf on a grid, where
+            // it integrates to one
+            let (mut all, mut cone, mut inside) = (0.0, 0.0, 0.0);
+            for k in 1..=n * n {
+                let u = [
+                    radical_inverse(k, 2),
+                    radical_inverse(k, 3),
+                    radical_inverse(k, 5),
+                ];
+                let wi = brdf.sample_direction(wo, u);
+                let pdf = brdf.direction_pdf(wo, wi);
+                assert!(wi.z > 0.0 && pdf > 0.0);
+                all += wi.z / pdf;
+                if wi.z > 0.5f64.sqrt() {
+                    cone += 1.0 / pdf;
+                    inside += 1.0;
+                }
+            }
+            let (mut all_pdf, mut cone_pdf) = (0.0, 0.0);
+            for a in 0..n {
+                for b in 0..n {
+                    let theta = (a as f64 + 0.5) / n as f64 * 0.5 * PI;
+                    let wi = direction(theta, (b as f64 + 0.5) / n as f64 * 2.0 * PI);
+                    let p = brdf.direction_pdf(wo, wi) * 0.5 * PI * 2.0 * PI * theta.sin();
+                    all_pdf += p;
+                    if wi.z > 0.5f64.sqrt() {
+                        cone_pdf += p;
+                    }
+                }
+            }
+            let samples = (n * n) as f64;
+            let close = |got: f64, want: f64| (got - want).abs() < 0.02 * want;
+            assert!(close(all / samples, PI), "{}", all / samples);
+            let cone_area = 2.0 * PI * (1.0 - 0.5f64.sqrt());
+            assert!(close(cone / samples, cone_area), "{}", cone / samples);
+            assert!(close(all_pdf / samples, 1.0), "{}", all_pdf / samples);
+            assert!(
+                close(cone_pdf / samples, inside / samples),
+                "{}",
+                cone_pdf / samples
+            );
+        }
+    }
+}
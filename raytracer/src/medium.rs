@@ -0,0 +1,419 @@
+// Heterogeneous participating media
+// Free-flight distances are sampled with delta tracking against a majorant (an upper
+// bound of the density): tentative collisions are accepted with probability density / majorant,
+// the rest are null collisions. Transmittance is estimated with ratio tracking.
+
+use std::fs;
+
+use crate::bsdf::*;
+use crate::bvh::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::phase::*;
+use crate::ray::*;
+use crate::shared_tools::*;
+use crate::spectrum::Dispersion;
+use crate::texture::*;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// the part of [t_min, t_max] inside a (convex) boundary
+fn inside_range(
+    boundary: &Arc<dyn Hittable>,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64)> {
+    let rec1 = boundary.hit(r, f64::MIN, f64::MAX)?;
+    let rec2 = boundary.hit(r, rec1.t + 0.0001, f64::MAX)?;
+    let t0 = rec1.t.max(t_min).max(0.0);
+    let t1 = rec2.t.min(t_max);
+    if t0 >= t1 {
+        None
+    } else {
+        Some((t0, t1))
+    }
+}
+
+pub trait DensityField: Send + Sync {
+    fn density(&self, p: Vec3) -> f64;
+    // an upper bound of density() over the whole medium
+    fn max_density(&self) -> f64;
+}
+
+// a dense grid of cell-centered samples inside [min, max], trilinearly interpolated
+pub struct GridDensity {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    pub min: Vec3,
+    pub max: Vec3,
+    pub data: Vec<f64>, // x fastest, then y, then z
+    max_value: f64,
+}
+impl DensityField for GridDensity {
+    fn density(&self, p: Vec3) -> f64 {
+        let rel = p - self.min;
+        let ext = self.max - self.min;
+        if rel.x < 0.0
+            || rel.y < 0.0
+            || rel.z < 0.0
+            || rel.x > ext.x
+            || rel.y > ext.y
+            || rel.z > ext.z
+        {
+            return 0.0;
+        }
+        let gx = rel.x / ext.x * self.nx as f64 - 0.5;
+        let gy = rel.y / ext.y * self.ny as f64 - 0.5;
+        let gz = rel.z / ext.z * self.nz as f64 - 0.5;
+        let (i, u) = Self::split(gx, self.nx);
+        let (j, v) = Self::split(gy, self.ny);
+        let (k, w) = Self::split(gz, self.nz);
+
+        let mut c = [[[0.0; 2]; 2]; 2];
+        for (di, ci) in c.iter_mut().enumerate() {
+            for (dj, cj) in ci.iter_mut().enumerate() {
+                for (dk, ck) in cj.iter_mut().enumerate() {
+                    *ck = self.at(
+                        (i + di).min(self.nx - 1),
+                        (j + dj).min(self.ny - 1),
+                        (k + dk).min(self.nz - 1),
+                    );
+                }
+            }
+        }
+        trilinear_interp(c, u, v, w)
+    }
+    fn max_density(&self) -> f64 {
+        self.max_value
+    }
+}
+impl GridDensity {
+    pub fn new(nx: usize, ny: usize, nz: usize, min: Vec3, max: Vec3, data: Vec<f64>) -> Self {
+        assert!(
+            nx >= 1 && ny >= 1 && nz >= 1,
+            "a density grid needs at least one sample"
+        );
+        assert_eq!(data.len(), nx * ny * nz);
+        let max_value = data.iter().cloned().fold(0.0, f64::max);
+        Self {
+            nx,
+            ny,
+            nz,
+            min,
+            max,
+            data,
+            max_value,
+        }
+    }
+
+    // the dense-grid binary format, all values little-endian:
+    //   b"DGRD", u32 nx, u32 ny, u32 nz,
+    //   f32 x 3 min corner, f32 x 3 max corner,
+    //   f32 x (nx * ny * nz) densities, x fastest
+    pub fn new_from_file(inputpath: &str) -> Result<Self, String> {
+        let bytes = fs::read(inputpath).map_err(|e| format!("{}: {}", inputpath, e))?;
+        if bytes.len() < 40 || &bytes[0..4] != b"DGRD" {
+            return Err("not a dense-grid file".to_string());
+        }
+        let read_u32 = |pos: usize| {
+            u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+        };
+        let read_f32 = |pos: usize| {
+            f32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]) as f64
+        };
+        let (nx, ny, nz) = (
+            read_u32(4) as usize,
+            read_u32(8) as usize,
+            read_u32(12) as usize,
+        );
+        let min = Vec3::new(read_f32(16), read_f32(20), read_f32(24));
+        let max = Vec3::new(read_f32(28), read_f32(32), read_f32(36));
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err("empty dense grid".to_string());
+        }
+        let n = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .unwrap_or(usize::MAX);
+        if n > (bytes.len() - 40) / 4 {
+            return Err("truncated dense-grid file".to_string());
+        }
+        let data = (0..n).map(|i| read_f32(40 + 4 * i)).collect();
+        Ok(Self::new(nx, ny, nz, min, max, data))
+    }
+
+    fn at(&self, i: usize, j: usize, k: usize) -> f64 {
+        self.data[(k * self.ny + j) * self.nx + i]
+    }
+
+    // return: (lower sample index, interpolation weight)
+    fn split(g: f64, n: usize) -> (usize, f64) {
+        if g <= 0.0 {
+            (0, 0.0)
+        } else if g >= (n - 1) as f64 {
+            (n - 1, 0.0)
+        } else {
+            (g.floor() as usize, g - g.floor())
+        }
+    }
+}
+
+// density from the luminance of any texture, e.g. NoiseTexture
+// max_value bounds the texture luminance (1.0 for NoiseTexture)
+pub struct TextureDensity {
+    pub tex: Arc<dyn Texture>,
+    pub scale: f64,
+    pub max_value: f64,
+}
+impl DensityField for TextureDensity {
+    fn density(&self, p: Vec3) -> f64 {
+        let c = self.tex.value(0.0, 0.0, p);
+        let luma = 0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z;
+        self.scale * clamp(luma, 0.0, self.max_value)
+    }
+    fn max_density(&self) -> f64 {
+        self.scale * self.max_value
+    }
+}
+impl TextureDensity {
+    pub fn new(tex: Arc<dyn Texture>, scale: f64, max_value: f64) -> Self {
+        Self {
+            tex,
+            scale,
+            max_value,
+        }
+    }
+}
+
+// billowy clouds straight from Perlin turbulence
+pub struct PerlinDensity {
+    pub noise: Perlin,
+    pub frequency: f64,
+    pub density: f64,
+    pub depth: i32,
+}
+impl DensityField for PerlinDensity {
+    fn density(&self, p: Vec3) -> f64 {
+        self.density * clamp(self.noise.turb(&(p * self.frequency), self.depth), 0.0, 1.0)
+    }
+    fn max_density(&self) -> f64 {
+        self.density
+    }
+}
+impl PerlinDensity {
+    pub fn new(frequency: f64, density: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            frequency,
+            density,
+            depth: 7,
+        }
+    }
+}
+
+pub struct HeterogeneousMedium {
+    pub boundary: Arc<dyn Hittable>,
+    pub density: Arc<dyn DensityField>,
+    pub phase_func: Arc<dyn Material>,
+    pub majorant: f64,
+}
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t0, t1) = inside_range(&self.boundary, r, t_min, t_max)?;
+        let ray_length = r.dir.length();
+
+        // delta tracking
+        let mut t = t0;
+        loop {
+            t -= (1.0 - rand::random::<f64>()).ln() / self.majorant / ray_length;
+            if t >= t1 {
+                return None;
+            }
+            let p = r.at(t);
+            if rand::random::<f64>() * self.majorant < self.density.density(p) {
+                return Some(HitRecord {
+                    t,
+                    p,
+                    normal: Vec3::new(1.0, 0.0, 0.0), // arbitrary
+                    front_face: true,                 // arbitrary
+                    tangent: Vec3::zero(),
+                    dpdu: Vec3::zero(),
+                    dpdv: Vec3::zero(),
+                    dndu: Vec3::zero(),
+                    dndv: Vec3::zero(),
+                    footprint: Footprint::point(),
+                    mat_ptr: self.phase_func.clone(),
+                    u: 0.0,
+                    v: 0.0,
+                });
+            }
+        }
+    }
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+}
+impl HeterogeneousMedium {
+    pub fn new(
+        boundary: Arc<dyn Hittable>,
+        density: Arc<dyn DensityField>,
+        mat_ptr: Arc<dyn Material>,
+    ) -> Self {
+        let majorant = density.max_density().max(1e-8);
+        Self {
+            boundary,
+            density,
+            phase_func: mat_ptr,
+            majorant,
+        }
+    }
+
+    // ratio tracking: an unbiased estimate of the transmittance between t_min and t_max,
+    // for visibility through the medium; the path tracer itself follows light-sampled
+    // directions with the same delta tracking as any other ray
+    pub fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let (t0, t1) = match inside_range(&self.boundary, r, t_min, t_max) {
+            Some(range) => range,
+            None => return 1.0,
+        };
+        let ray_length = r.dir.length();
+        let mut tr = 1.0;
+        let mut t = t0;
+        loop {
+            t -= (1.0 - rand::random::<f64>()).ln() / self.majorant / ray_length;
+            if t >= t1 {
+                return tr;
+            }
+            tr *= 1.0 - self.density.density(r.at(t)) / self.majorant;
+        }
+    }
+}
+
+// a homogeneous medium with per-channel absorption and scattering coefficients that may glow:
+// fire, colored smoke, tinted liquids
+// the boundary is no surface: crossing it puts the ray inside, where walk_to_surface in main
+// samples distances in a random channel per walk, weighted by the balance heuristic over all
+// three, as in a scattering Dielectric
+pub struct ChromaticMedium {
+    pub boundary: Arc<dyn Hittable>,
+    interface: Arc<dyn Material>,
+}
+impl Hittable for ChromaticMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut rec = self.boundary.hit(r, t_min, t_max)?;
+        rec.mat_ptr = self.interface.clone();
+        Some(rec)
+    }
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+}
+impl ChromaticMedium {
+    pub fn new(
+        boundary: Arc<dyn Hittable>,
+        sigma_a: Vec3,
+        sigma_s: Vec3,
+        emission: Vec3,
+        phase: Arc<dyn PhaseFunction>,
+    ) -> Self {
+        Self {
+            boundary,
+            interface: Arc::new(MediumInterface {
+                sigma_a,
+                sigma_s,
+                emission,
+                phase,
+            }),
+        }
+    }
+
+    // a clear liquid or gas that only absorbs
+    pub fn new_absorbing(boundary: Arc<dyn Hittable>, sigma_a: Vec3) -> Self {
+        Self::new(
+            boundary,
+            sigma_a,
+            Vec3::zero(),
+            Vec3::zero(),
+            Arc::new(IsotropicPhase::new()),
+        )
+    }
+}
+
+// the boundary of a ChromaticMedium: passed straight through, into or out of the medium
+struct MediumInterface {
+    sigma_a: Vec3,  // absorption per unit length
+    sigma_s: Vec3,  // scattering per unit length
+    emission: Vec3, // radiance emitted where light is absorbed
+    phase: Arc<dyn PhaseFunction>,
+}
+impl Material for MediumInterface {
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        let mut bsdf = Bsdf::new(
+            self,
+            ray_in,
+            rec,
+            BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION,
+        );
+        let me = NestedMedium {
+            id: self as *const Self as usize,
+            priority: 0,
+            ref_idx: 1.0,
+            dispersion: Dispersion::None,
+            absorption: self.sigma_a,
+            scattering: self.sigma_s,
+            anisotropy: 0.0,
+            emission: self.emission,
+            phase: Some(self.phase.clone()),
+        };
+        bsdf.set_boundary(me, ray_in, false);
+        // no refraction here, whatever the ray comes from
+        bsdf.pass_through = true;
+        Some(bsdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ratio_tracking_transmittance() {
+        let density = 0.7;
+        let grid = GridDensity::new(
+            2,
+            2,
+            2,
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::ones(),
+            vec![density; 8],
+        );
+        let mut medium = HeterogeneousMedium::new(
+            Arc::new(Sphere::new(
+                Vec3::zero(),
+                1.0,
+                Arc::new(Lambertian::new(Vec3::ones())),
+            )),
+            Arc::new(grid),
+            Arc::new(Isotropic::new_from_color(Vec3::ones())),
+        );
+        // through the middle: 2 long, and 1 from the center on
+        let r = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let n = 100000;
+        for majorant in &[density, 2.5 * density] {
+            medium.majorant = *majorant;
+            for (t_min, length) in &[(0.0, 2.0), (5.0, 1.0)] {
+                let mean = (0..n)
+                    .map(|_| medium.transmittance(&r, *t_min, f64::MAX))
+                    .sum::<f64>()
+                    / n as f64;
+                let want = (-density * length).exp();
+                assert!((mean - want).abs() < 0.01, "{} != {}", mean, want);
+            }
+        }
+        // the ray misses the medium
+        let r = Ray::new(Vec3::new(-5.0, 2.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(medium.transmittance(&r, 0.0, f64::MAX), 1.0);
+    }
+}
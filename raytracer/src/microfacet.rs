@@ -0,0 +1,139 @@
+// The GGX (Trowbridge-Reitz) microfacet distribution
+// Directions are in the local shading frame: z along the normal. Sampling follows Heitz,
+// "Sampling the GGX Distribution of Visible Normals" (JCGT 2018); shadowing-masking is the
+// height-correlated Smith form.
+
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+impl Ggx {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        // a perfect mirror is out of reach of the pdf, keep a tiny lobe
+        Self {
+            alpha_x: alpha_x.max(1e-3),
+            alpha_y: alpha_y.max(1e-3),
+        }
+    }
+
+    // roughness is perceptually linear, alpha = roughness^2
+    pub fn from_roughness(roughness_x: f64, roughness_y: f64) -> Self {
+        Self::new(roughness_x * roughness_x, roughness_y * roughness_y)
+    }
+
+    // the density of microfacet normals h
+    pub fn d(&self, h: Vec3) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let x = h.x / self.alpha_x;
+        let y = h.y / self.alpha_y;
+        let t = x * x + y * y + h.z * h.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * t * t)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        if w.z.abs() < 1e-12 {
+            return f64::MAX;
+        }
+        let a2 = (self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2);
+        0.5 * (-1.0 + (1.0 + a2 / (w.z * w.z)).sqrt())
+    }
+
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // a microfacet normal visible from wo (wo.z > 0), distributed as pdf_visible
+    // u1, u2: uniform in [0, 1)
+    pub fn sample_visible(&self, wo: Vec3, u1: f64, u2: f64) -> Vec3 {
+        // to the hemisphere configuration
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit();
+        let lensq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if lensq > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / lensq.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+        // a point on the projected area
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        // back to the ellipsoid configuration
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).unit()
+    }
+
+    // the density of visible normals: G1(wo) max(0, wo * h) D(h) / wo.z
+    pub fn pdf_visible(&self, wo: Vec3, h: Vec3) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * (wo * h).max(0.0) * self.d(h) / wo.z
+    }
+}
+
+// reflect wo about h, both pointing away from the surface
+pub fn reflect_local(wo: Vec3, h: Vec3) -> Vec3 {
+    h * (2.0 * (wo * h)) - wo
+}
+
+// Fresnel reflectance of a conductor with complex index eta + i k, per channel
+pub fn fresnel_conductor(cos_i: f64, eta: Vec3, k: Vec3) -> Vec3 {
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_i * cos_i;
+        let sin2 = 1.0 - cos2;
+        let eta2 = eta * eta;
+        let k2 = k * k;
+        let t0 = eta2 - k2 - sin2;
+        let a2plusb2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let t1 = a2plusb2 + cos2;
+        let a = (0.5 * (a2plusb2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2plusb2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    Vec3::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+// refract wo through a microfacet h, etap = n_t / n_i; None on total internal reflection
+pub fn refract_local(wo: Vec3, h: Vec3, etap: f64) -> Option<Vec3> {
+    let cos_i = wo * h;
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (etap * etap);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-wo / etap + h * (cos_i / etap - cos_t))
+}
+
+// exact Fresnel reflectance of a dielectric interface, etap = n_t / n_i
+pub fn fresnel_dielectric(cos_i: f64, etap: f64) -> f64 {
+    let cos_i = cos_i.abs().min(1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (etap * etap);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parl = (etap * cos_i - cos_t) / (etap * cos_i + cos_t);
+    let r_perp = (cos_i - etap * cos_t) / (cos_i + etap * cos_t);
+    0.5 * (r_parl * r_parl + r_perp * r_perp)
+}
@@ -18,7 +18,24 @@ impl ONB {
         Self { u, v, w }
     }
 
+    // w along the normal, u as close to the tangent as possible
+    pub fn build_from_w_and_tangent(normal: &Vec3, tangent: &Vec3) -> Self {
+        let w = normal.unit();
+        let u = *tangent - w * (*tangent * w);
+        if u.squared_length() < 1e-12 {
+            return Self::build_from_w(normal);
+        }
+        let u = u.unit();
+        let v = w.cross(u);
+        Self { u, v, w }
+    }
+
     pub fn local(&self, a: &Vec3) -> Vec3 {
         self.u * a.x + self.v * a.y + self.w * a.z
     }
+
+    // the inverse of local()
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(*a * self.u, *a * self.v, *a * self.w)
+    }
 }
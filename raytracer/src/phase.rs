@@ -0,0 +1,151 @@
+// Phase functions for participating media
+// Directions follow the light: wo is the direction the ray was travelling in,
+// wi the direction it leaves in, so cos_theta = wo * wi and g > 0 scatters forward.
+// All phase functions here are normalized over the sphere.
+
+use crate::onb::*;
+use crate::shared_tools::*;
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+
+pub trait PhaseFunction: Send + Sync {
+    // return: the value of the phase function
+    fn eval(&self, wo: Vec3, wi: Vec3) -> f64;
+    // return: a direction wi distributed as pdf(), u: uniform in [0, 1)
+    fn sample(&self, wo: Vec3, u: [f64; 2]) -> Vec3;
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64;
+}
+
+fn cos_between(wo: Vec3, wi: Vec3) -> f64 {
+    wo.unit() * wi.unit()
+}
+
+// a direction making cos_theta with w, u picks the azimuth
+fn direction_from_cos(w: Vec3, cos_theta: f64, u: f64) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u;
+    ONB::build_from_w(&w).local(&Vec3::new(
+        sin_theta * phi.cos(),
+        sin_theta * phi.sin(),
+        cos_theta,
+    ))
+}
+
+#[derive(Default)]
+pub struct IsotropicPhase {}
+impl PhaseFunction for IsotropicPhase {
+    fn eval(&self, _wo: Vec3, _wi: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+    fn sample(&self, _wo: Vec3, u: [f64; 2]) -> Vec3 {
+        let z = 1.0 - 2.0 * u[0];
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u[1];
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+    fn pdf(&self, _wo: Vec3, _wi: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+impl IsotropicPhase {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+// g in (-1, 1): the mean cosine of the scattering angle
+pub struct HenyeyGreenstein {
+    pub g: f64,
+}
+impl PhaseFunction for HenyeyGreenstein {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> f64 {
+        henyey_greenstein(cos_between(wo, wi), self.g)
+    }
+    fn sample(&self, wo: Vec3, u: [f64; 2]) -> Vec3 {
+        direction_from_cos(wo, sample_henyey_greenstein(self.g, u[0]), u[1])
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        self.eval(wo, wi)
+    }
+}
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        Self {
+            g: clamp(g, -0.999, 0.999),
+        }
+    }
+}
+
+pub fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+}
+
+// inverts the CDF of the HG distribution at u, return: cos_theta
+fn sample_henyey_greenstein(g: f64, u: f64) -> f64 {
+    if g.abs() < 1e-3 {
+        return 1.0 - 2.0 * u;
+    }
+    let sqr = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+    clamp((1.0 + g * g - sqr * sqr) / (2.0 * g), -1.0, 1.0)
+}
+
+// a blend of a forward and a backward lobe, e.g. for clouds (bright silver lining and glory)
+pub struct DoubleHenyeyGreenstein {
+    pub g1: f64,
+    pub g2: f64,
+    pub weight: f64, // of the first lobe
+}
+impl PhaseFunction for DoubleHenyeyGreenstein {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let cos_theta = cos_between(wo, wi);
+        self.weight * henyey_greenstein(cos_theta, self.g1)
+            + (1.0 - self.weight) * henyey_greenstein(cos_theta, self.g2)
+    }
+    fn sample(&self, wo: Vec3, u: [f64; 2]) -> Vec3 {
+        // pick the lobe with u[0] and stretch what is left of it back to [0, 1)
+        let (g, u0) = if u[0] < self.weight {
+            (self.g1, u[0] / self.weight)
+        } else {
+            (self.g2, (u[0] - self.weight) / (1.0 - self.weight))
+        };
+        direction_from_cos(wo, sample_henyey_greenstein(g, u0), u[1])
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        self.eval(wo, wi)
+    }
+}
+impl DoubleHenyeyGreenstein {
+    pub fn new(g1: f64, g2: f64, weight: f64) -> Self {
+        Self {
+            g1: clamp(g1, -0.999, 0.999),
+            g2: clamp(g2, -0.999, 0.999),
+            weight: clamp(weight, 0.0, 1.0),
+        }
+    }
+}
+
+// scattering by particles much smaller than the wavelength (clear sky)
+#[derive(Default)]
+pub struct Rayleigh {}
+impl PhaseFunction for Rayleigh {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let cos_theta = cos_between(wo, wi);
+        3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta)
+    }
+    fn sample(&self, wo: Vec3, u: [f64; 2]) -> Vec3 {
+        // the CDF (cos^3 + 3 cos + 4) / 8 = u is a depressed cubic with one real root
+        let q = 4.0 - 8.0 * u[0];
+        let d = (0.25 * q * q + 1.0).sqrt();
+        let cos_theta = (-0.5 * q + d).cbrt() + (-0.5 * q - d).cbrt();
+        direction_from_cos(wo, clamp(cos_theta, -1.0, 1.0), u[1])
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        self.eval(wo, wi)
+    }
+}
+impl Rayleigh {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
@@ -0,0 +1,486 @@
+// A principled uber material after Burley, "Physically-Based Shading at Disney" (2012) and
+// "Extending the Disney BRDF to a BSDF with Integrated Subsurface Scattering" (2015).
+// Lobes: Burley diffuse + sheen, GGX specular, GTR1 clearcoat and a rough dielectric for
+// transmission. Every parameter is a texture; scalar parameters are read from the first channel.
+// The target for glTF materials, see GltfPbr.
+
+use crate::bsdf::*;
+use crate::hittable::HitRecord;
+use crate::material::*;
+use crate::microfacet::*;
+use crate::ray::*;
+use crate::shared_tools::*;
+use crate::spectrum::Dispersion;
+use crate::texture::*;
+use crate::vec3::*;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>,
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub ior: Arc<dyn Texture>,
+    pub emission: Arc<dyn Texture>,
+}
+impl Material for Principled {
+    fn bsdf<'a>(&'a self, ray_in: &Ray, rec: &'a HitRecord) -> Option<Bsdf<'a>> {
+        let lobes = self.lobes(rec);
+        let mut flags = BsdfFlags::DIFFUSE | BsdfFlags::GLOSSY | BsdfFlags::REFLECTION;
+        if lobes.transmission_weight() > 0.0 {
+            flags = flags | BsdfFlags::TRANSMISSION;
+        }
+        let mut bsdf = Bsdf::new(self, ray_in, rec, flags);
+        if lobes.transmission_weight() > 0.0 {
+            // transmission is a rough dielectric boundary
+            let me = NestedMedium {
+                id: self as *const Self as usize,
+                priority: 0,
+                ref_idx: lobes.ior,
+                dispersion: Dispersion::None,
+                absorption: Vec3::zero(),
+                scattering: Vec3::zero(),
+                anisotropy: 0.0,
+                emission: Vec3::zero(),
+                phase: None,
+            };
+            bsdf.set_boundary(me, ray_in, false);
+        }
+        Some(bsdf)
+    }
+    fn sample(&self, bsdf: &Bsdf, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        let lobes = self.lobes(bsdf.rec);
+        let wi = lobes.sample(wo, bsdf.eta, u)?;
+        Some(BsdfSample::new(
+            wi,
+            lobes.eval(wo, wi, bsdf.eta),
+            lobes.pdf(wo, wi, bsdf.eta),
+            BsdfFlags::GLOSSY | side(wi),
+        ))
+    }
+    fn eval(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> Vec3 {
+        self.lobes(bsdf.rec).eval(wo, wi, bsdf.eta)
+    }
+    fn pdf(&self, bsdf: &Bsdf, wo: Vec3, wi: Vec3) -> f64 {
+        self.lobes(bsdf.rec).pdf(wo, wi, bsdf.eta)
+    }
+    fn emitted(&self, _ray_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Vec3) -> Vec3 {
+        self.emission.filtered(u, v, p, &rec.footprint)
+    }
+}
+impl Principled {
+    // a plain dielectric or metal; the other parameters are left at their defaults
+    pub fn new(base_color: Vec3, metallic: f64, roughness: f64) -> Self {
+        Self::new_from_texture(
+            Arc::new(SolidColor::new(base_color)),
+            constant(metallic),
+            constant(roughness),
+        )
+    }
+    pub fn new_from_texture(
+        base_color: Arc<dyn Texture>,
+        metallic: Arc<dyn Texture>,
+        roughness: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            base_color,
+            metallic,
+            roughness,
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_gloss: constant(1.0),
+            transmission: constant(0.0),
+            ior: constant(1.5),
+            emission: Arc::new(SolidColor::new(Vec3::zero())),
+        }
+    }
+
+    // the parameters at a hit point
+    pub fn lobes(&self, rec: &HitRecord) -> PrincipledLobes {
+        let scalar = |t: &Arc<dyn Texture>| {
+            clamp(t.filtered(rec.u, rec.v, rec.p, &rec.footprint).x, 0.0, 1.0)
+        };
+        let roughness = scalar(&self.roughness);
+        PrincipledLobes {
+            base_color: self
+                .base_color
+                .filtered(rec.u, rec.v, rec.p, &rec.footprint),
+            metallic: scalar(&self.metallic),
+            roughness,
+            specular: scalar(&self.specular),
+            specular_tint: scalar(&self.specular_tint),
+            sheen: scalar(&self.sheen),
+            sheen_tint: scalar(&self.sheen_tint),
+            clearcoat: scalar(&self.clearcoat),
+            clearcoat_gloss: scalar(&self.clearcoat_gloss),
+            transmission: scalar(&self.transmission),
+            ior: self
+                .ior
+                .filtered(rec.u, rec.v, rec.p, &rec.footprint)
+                .x
+                .max(1.0),
+            ggx: Ggx::from_roughness(roughness, roughness),
+        }
+    }
+}
+
+// a glTF 2.0 material: the metallic-roughness model and the KHR_materials_clearcoat, _sheen,
+// _transmission, _ior and _emissive_strength extensions, with the spec's defaults. Textures
+// are multiplied by their factors, as in glTF.
+pub struct GltfPbr {
+    pub base_color_factor: Vec3,
+    pub base_color_texture: Option<Arc<dyn Texture>>,
+    pub metallic_factor: f64,
+    pub roughness_factor: f64,
+    // metalness in the blue channel, roughness in the green one
+    pub metallic_roughness_texture: Option<Arc<dyn Texture>>,
+    pub emissive_factor: Vec3,
+    pub emissive_texture: Option<Arc<dyn Texture>>,
+    pub emissive_strength: f64,
+    pub clearcoat_factor: f64,
+    pub clearcoat_roughness_factor: f64,
+    pub sheen_color_factor: Vec3,
+    pub transmission_factor: f64,
+    pub ior: f64,
+}
+impl Default for GltfPbr {
+    fn default() -> Self {
+        Self {
+            base_color_factor: Vec3::ones(),
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            emissive_factor: Vec3::zero(),
+            emissive_texture: None,
+            emissive_strength: 1.0,
+            clearcoat_factor: 0.0,
+            clearcoat_roughness_factor: 0.0,
+            sheen_color_factor: Vec3::zero(),
+            transmission_factor: 0.0,
+            ior: 1.5,
+        }
+    }
+}
+
+// a glTF texture times its factor, one channel of it spread over all three for a scalar
+struct GltfTexture {
+    texture: Arc<dyn Texture>,
+    factor: Vec3,
+    channel: Option<usize>,
+}
+impl Texture for GltfTexture {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        self.pick(self.texture.value(u, v, p))
+    }
+    fn filtered(&self, u: f64, v: f64, p: Vec3, footprint: &Footprint) -> Vec3 {
+        self.pick(self.texture.filtered(u, v, p, footprint))
+    }
+}
+impl GltfTexture {
+    fn pick(&self, value: Vec3) -> Vec3 {
+        match self.channel {
+            Some(channel) => self.factor * value[channel],
+            None => value.elemul(self.factor),
+        }
+    }
+}
+
+impl Principled {
+    pub fn new_from_gltf(material: &GltfPbr) -> Self {
+        let parameter = |texture: &Option<Arc<dyn Texture>>, factor: Vec3, channel| {
+            let ret: Arc<dyn Texture> = match texture {
+                Some(texture) => Arc::new(GltfTexture {
+                    texture: texture.clone(),
+                    factor,
+                    channel,
+                }),
+                None => Arc::new(SolidColor::new(factor)),
+            };
+            ret
+        };
+        let metallic_roughness = &material.metallic_roughness_texture;
+        let mut ret = Self::new_from_texture(
+            parameter(
+                &material.base_color_texture,
+                material.base_color_factor,
+                None,
+            ),
+            parameter(
+                metallic_roughness,
+                Vec3::ones() * material.metallic_factor,
+                Some(2),
+            ),
+            parameter(
+                metallic_roughness,
+                Vec3::ones() * material.roughness_factor,
+                Some(1),
+            ),
+        );
+        ret.emission = parameter(
+            &material.emissive_texture,
+            material.emissive_factor * material.emissive_strength,
+            None,
+        );
+        // glTF derives the reflectance at normal incidence from the ior, specular = 0.5 is 0.04
+        let ior = material.ior.max(1.0);
+        ret.specular = constant(((ior - 1.0) / (ior + 1.0)).powi(2) / 0.08);
+        ret.ior = constant(ior);
+        ret.transmission = constant(material.transmission_factor);
+        // the same clearcoat alpha: roughness^2 in glTF, from 0.1 to 0.001 with the gloss here
+        ret.clearcoat = constant(material.clearcoat_factor);
+        let alpha = material.clearcoat_roughness_factor.powi(2);
+        ret.clearcoat_gloss = constant(clamp((0.1 - alpha) / 0.099, 0.0, 1.0));
+        // the sheen here is white or tinted towards the base color, only its strength carries over
+        let sheen = material.sheen_color_factor;
+        ret.sheen = constant(sheen.x.max(sheen.y).max(sheen.z));
+        ret.sheen_tint = constant(0.0);
+        ret
+    }
+}
+
+// a scalar parameter
+pub fn constant(value: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new_from_f64(value, value, value))
+}
+
+fn schlick_weight(cos: f64) -> f64 {
+    let m = clamp(1.0 - cos, 0.0, 1.0);
+    m * m * m * m * m
+}
+
+fn mix(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+    a * (1.0 - t) + b * t
+}
+
+// the clearcoat distribution (GTR with gamma = 1)
+fn gtr1(cos_h: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h))
+}
+
+fn sample_gtr1(alpha: f64, u1: f64, u2: f64) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos_h = ((1.0 - a2.powf(1.0 - u1)) / (1.0 - a2)).max(0.0).sqrt();
+    let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h)
+}
+
+// separable Smith G1 with a fixed alpha, divided by 2 cos as in the Disney code
+fn smith_g_ggx(cos: f64, alpha: f64) -> f64 {
+    let a = alpha * alpha;
+    let b = cos * cos;
+    1.0 / (cos + (a + b - a * b).sqrt())
+}
+
+// the parameters of a Principled material evaluated at a point
+// directions are local to the shading frame, wo towards the viewer
+#[derive(Clone, Copy)]
+pub struct PrincipledLobes {
+    pub base_color: Vec3,
+    pub metallic: f64,
+    pub roughness: f64,
+    pub specular: f64,
+    pub specular_tint: f64,
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    pub clearcoat: f64,
+    pub clearcoat_gloss: f64,
+    pub transmission: f64,
+    pub ior: f64,
+    pub ggx: Ggx,
+}
+impl PrincipledLobes {
+    fn diffuse_weight(&self) -> f64 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+    fn transmission_weight(&self) -> f64 {
+        (1.0 - self.metallic) * self.transmission
+    }
+    // the transmissive part carries its own reflection, keep the specular lobe off it
+    fn specular_weight(&self) -> f64 {
+        1.0 - self.transmission_weight()
+    }
+    fn clearcoat_weight(&self) -> f64 {
+        0.25 * self.clearcoat
+    }
+    fn clearcoat_alpha(&self) -> f64 {
+        0.1 * (1.0 - self.clearcoat_gloss) + 0.001 * self.clearcoat_gloss
+    }
+
+    // the chance of sampling the transmission lobe rather than the reflection lobes
+    fn transmission_probability(&self) -> f64 {
+        let total = self.diffuse_weight()
+            + self.specular_weight()
+            + self.clearcoat_weight()
+            + self.transmission_weight();
+        self.transmission_weight() / total
+    }
+
+    // the probabilities of the reflection lobes: diffuse, specular, clearcoat
+    fn reflection_probabilities(&self) -> (f64, f64, f64) {
+        let d = self.diffuse_weight();
+        let s = self.specular_weight();
+        let c = self.clearcoat_weight();
+        let total = d + s + c;
+        if total <= 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        (d / total, s / total, c / total)
+    }
+
+    // return: BSDF * |cos(wi)| over all the lobes, eta = n_t / n_i across the surface
+    pub fn eval(&self, wo: Vec3, wi: Vec3, eta: f64) -> Vec3 {
+        let mut ret = self.eval_reflection(wo, wi);
+        if self.transmission_weight() > 0.0 {
+            ret += RoughDielectric::eval_microfacet(&self.ggx, wo, wi, eta).elemul(self.base_color)
+                * self.transmission_weight();
+        }
+        ret
+    }
+
+    // the density of wi as sampled by sample()
+    pub fn pdf(&self, wo: Vec3, wi: Vec3, eta: f64) -> f64 {
+        let p_transmission = self.transmission_probability();
+        let mut ret = (1.0 - p_transmission) * self.pdf_reflection(wo, wi);
+        if p_transmission > 0.0 {
+            ret += p_transmission * RoughDielectric::pdf_microfacet(&self.ggx, wo, wi, eta);
+        }
+        ret
+    }
+
+    // pick the transmission lobe or one of the reflection lobes with u[0]
+    pub fn sample(&self, wo: Vec3, eta: f64, u: [f64; 3]) -> Option<Vec3> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let p = self.transmission_probability();
+        if u[0] < p {
+            RoughDielectric::sample_microfacet(&self.ggx, wo, eta, [u[0] / p, u[1], u[2]])
+        } else {
+            Some(self.sample_reflection(wo, [(u[0] - p) / (1.0 - p), u[1], u[2]]))
+        }
+    }
+
+    // the lobes on the outside: diffuse, sheen, specular and clearcoat
+    fn eval_reflection(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::zero();
+        }
+        let h = (wo + wi).unit();
+        let cos_d = wi * h;
+        let lum = 0.3 * self.base_color.x + 0.6 * self.base_color.y + 0.1 * self.base_color.z;
+        let tint = if lum > 0.0 {
+            self.base_color / lum
+        } else {
+            Vec3::ones()
+        };
+
+        // Burley diffuse with retro-reflection, and the sheen at grazing angles
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z))
+            * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+        let sheen = mix(Vec3::ones(), tint, self.sheen_tint) * (self.sheen * schlick_weight(cos_d));
+        let diffuse = (self.base_color * (fd / PI) + sheen) * self.diffuse_weight();
+
+        // specular: F0 from the specular level (0.5 is 4%) blends to the base color with metallic
+        let spec0 = mix(
+            mix(Vec3::ones(), tint, self.specular_tint) * (0.08 * self.specular),
+            self.base_color,
+            self.metallic,
+        );
+        let f = mix(spec0, Vec3::ones(), schlick_weight(cos_d));
+        let specular =
+            f * (self.ggx.d(h) * self.ggx.g(wo, wi) / (4.0 * wo.z * wi.z) * self.specular_weight());
+
+        let clearcoat = self.clearcoat_weight()
+            * gtr1(h.z, self.clearcoat_alpha())
+            * (0.04 + 0.96 * schlick_weight(cos_d))
+            * smith_g_ggx(wo.z, 0.25)
+            * smith_g_ggx(wi.z, 0.25);
+
+        (diffuse + specular + Vec3::ones() * clearcoat) * wi.z
+    }
+
+    fn pdf_reflection(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).unit();
+        let (p_d, p_s, p_c) = self.reflection_probabilities();
+        p_d * wi.z / PI
+            + p_s * self.ggx.pdf_visible(wo, h) / (4.0 * (wo * h))
+            + p_c * gtr1(h.z, self.clearcoat_alpha()) * h.z / (4.0 * (wo * h))
+    }
+
+    fn sample_reflection(&self, wo: Vec3, u: [f64; 3]) -> Vec3 {
+        let (p_d, p_s, _) = self.reflection_probabilities();
+        if u[0] < p_d {
+            Vec3::cosine_direction(u[1], u[2])
+        } else if u[0] < p_d + p_s {
+            reflect_local(wo, self.ggx.sample_visible(wo, u[1], u[2]))
+        } else {
+            reflect_local(wo, sample_gtr1(self.clearcoat_alpha(), u[1], u[2]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(texture: &Arc<dyn Texture>) -> Vec3 {
+        texture.value(0.3, 0.6, Vec3::zero())
+    }
+
+    #[test]
+    fn gltf_mapping() {
+        // the glTF defaults: a rough white metal with a reflectance of 0.04
+        let m = Principled::new_from_gltf(&GltfPbr::default());
+        assert_eq!(at(&m.base_color), Vec3::ones());
+        assert_eq!(at(&m.metallic).x, 1.0);
+        assert_eq!(at(&m.roughness).x, 1.0);
+        assert!((at(&m.specular).x - 0.5).abs() < 1e-12);
+        assert_eq!(at(&m.clearcoat).x, 0.0);
+        assert_eq!(at(&m.emission), Vec3::zero());
+
+        // metalness from blue, roughness from green, both times their factors
+        let m = Principled::new_from_gltf(&GltfPbr {
+            base_color_factor: Vec3::new(0.5, 0.5, 1.0),
+            base_color_texture: Some(Arc::new(SolidColor::new(Vec3::new(0.2, 0.4, 0.6)))),
+            metallic_factor: 0.5,
+            roughness_factor: 0.5,
+            metallic_roughness_texture: Some(Arc::new(SolidColor::new(Vec3::new(0.0, 0.6, 0.8)))),
+            emissive_factor: Vec3::new(1.0, 0.5, 0.0),
+            emissive_strength: 4.0,
+            clearcoat_factor: 1.0,
+            clearcoat_roughness_factor: 0.1,
+            sheen_color_factor: Vec3::new(0.2, 0.7, 0.1),
+            transmission_factor: 0.9,
+            ior: 1.33,
+            ..GltfPbr::default()
+        });
+        assert!((at(&m.base_color) - Vec3::new(0.1, 0.2, 0.6)).length() < 1e-12);
+        assert!((at(&m.metallic).x - 0.4).abs() < 1e-12);
+        assert!((at(&m.roughness).x - 0.3).abs() < 1e-12);
+        assert_eq!(at(&m.emission), Vec3::new(4.0, 2.0, 0.0));
+        assert_eq!(at(&m.transmission).x, 0.9);
+        assert_eq!(at(&m.ior).x, 1.33);
+        assert!((at(&m.specular).x - (0.33f64 / 2.33).powi(2) / 0.08).abs() < 1e-12);
+        assert_eq!(at(&m.sheen).x, 0.7);
+        // clearcoat roughness 0.1 is alpha 0.01, which the gloss has to give back
+        let gloss = at(&m.clearcoat_gloss).x;
+        assert!((0.1 * (1.0 - gloss) + 0.001 * gloss - 0.01).abs() < 1e-12);
+    }
+}
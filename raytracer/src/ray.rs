@@ -1,9 +1,67 @@
+use crate::phase::*;
+use crate::spectrum::*;
 use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// a dielectric volume the ray is travelling inside of, or a participating medium without a
+// surface (ChromaticMedium)
+#[derive(Clone)]
+pub struct NestedMedium {
+    pub id: usize, // tells the dielectric materials apart
+    pub priority: u32,
+    pub ref_idx: f64,
+    pub dispersion: Dispersion,
+    pub absorption: Vec3,
+    pub scattering: Vec3,
+    pub anisotropy: f64,
+    pub emission: Vec3, // radiance emitted where light is absorbed
+    // the phase function of the scattering, Henyey-Greenstein with anisotropy if None
+    pub phase: Option<Arc<dyn PhaseFunction>>,
+}
+impl NestedMedium {
+    pub fn ior(&self, wavelength: Option<f64>) -> f64 {
+        match wavelength {
+            Some(lambda) => self.dispersion.ior(self.ref_idx, lambda),
+            None => self.ref_idx,
+        }
+    }
+}
+
+// the one with the highest priority, the latest entered of equals
+pub fn top_medium<'a>(media: impl Iterator<Item = &'a NestedMedium>) -> Option<&'a NestedMedium> {
+    let mut ret: Option<&NestedMedium> = None;
+    for m in media {
+        match ret {
+            Some(cur) if cur.priority > m.priority => {}
+            _ => ret = Some(m),
+        }
+    }
+    ret
+}
+
+// rays offset by one sample spacing in x and y on the image, following the main ray through
+// specular bounces (Igehy 1999), for the footprint of textures
+#[derive(Clone, Copy)]
+pub struct RayDifferential {
+    pub rx_origin: Vec3,
+    pub rx_dir: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_dir: Vec3,
+}
 
 #[derive(Clone)]
 pub struct Ray {
     pub orig: Vec3,
     pub dir: Vec3,
+    // the stack of nested dielectrics the ray is inside of
+    pub media: Vec<NestedMedium>,
+    // in nanometers, set once the path has been split by dispersion
+    // (or from the start in spectral mode, where it is the hero wavelength)
+    pub wavelength: Option<f64>,
+    // the path went through a dispersive surface, only `wavelength` is still valid
+    pub dispersed: bool,
+    // None after anything but a specular bounce
+    pub differential: Option<RayDifferential>,
 }
 
 impl Ray {
@@ -11,6 +69,84 @@ impl Ray {
         Self {
             orig: _orig,
             dir: _dir,
+            media: Vec::new(),
+            wavelength: None,
+            dispersed: false,
+            differential: None,
+        }
+    }
+
+    // a ray continuing the same path, in the same media
+    pub fn spawn(&self, orig: Vec3, dir: Vec3) -> Self {
+        Self {
+            orig,
+            dir,
+            media: self.media.clone(),
+            wavelength: self.wavelength,
+            dispersed: self.dispersed,
+            differential: None,
+        }
+    }
+
+    // the medium that fills the space around the origin
+    pub fn current_medium(&self) -> Option<&NestedMedium> {
+        top_medium(self.media.iter())
+    }
+
+    // Beer–Lambert: the fraction of light left after travelling to at(t)
+    pub fn transmittance(&self, t: f64) -> Vec3 {
+        match self.current_medium() {
+            Some(m) => {
+                let d = t * self.dir.length();
+                Vec3::new(
+                    (-m.absorption.x * d).exp(),
+                    (-m.absorption.y * d).exp(),
+                    (-m.absorption.z * d).exp(),
+                )
+            }
+            None => Vec3::ones(),
+        }
+    }
+
+    // the way to at(t) through the current medium: absorbed, and in a scattering or glowing
+    // medium maybe scattered on the way, one step of a random walk
+    // channel: the color channel the distance is sampled in
+    // return: the ray scattered before at(t) if any, the path throughput up to there, its
+    // pdf had each channel been the one sampled, see walk_to_surface in main, and the light
+    // emitted where it scattered, to be weighted like the throughput
+    pub fn free_flight(&self, t: f64, channel: usize) -> (Option<Ray>, Vec3, Vec3, Vec3) {
+        let m = match self.current_medium() {
+            Some(m) if m.scattering * Vec3::ones() + m.emission * m.absorption > 0.0 => m,
+            _ => return (None, self.transmittance(t), Vec3::ones(), Vec3::zero()),
+        };
+        let sigma_t = m.absorption + m.scattering;
+        let tr = |d: f64| {
+            Vec3::new(
+                (-sigma_t.x * d).exp(),
+                (-sigma_t.y * d).exp(),
+                (-sigma_t.z * d).exp(),
+            )
+        };
+        let length = self.dir.length();
+        let d = t * length;
+        let s = -(1.0 - rand::random::<f64>()).ln() / sigma_t[channel];
+        if s < d {
+            let t_s = tr(s);
+            let wo = self.dir / length;
+            let u = [rand::random::<f64>(), rand::random::<f64>()];
+            let dir = match &m.phase {
+                Some(phase) => phase.sample(wo, u),
+                None => HenyeyGreenstein::new(m.anisotropy).sample(wo, u),
+            };
+            (
+                Some(self.spawn(self.at(s / length), dir)),
+                m.scattering.elemul(t_s),
+                sigma_t.elemul(t_s),
+                m.absorption.elemul(m.emission).elemul(t_s),
+            )
+        } else {
+            let t_d = tr(d);
+            (None, t_d, t_d, Vec3::zero())
         }
     }
 
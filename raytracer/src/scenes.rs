@@ -1,10 +1,23 @@
+use crate::bump::*;
 use crate::bvh::*;
+use crate::curve::*;
+use crate::hair::*;
+use crate::heightfield::*;
 use crate::hittable::*;
 use crate::material::*;
+use crate::measured::*;
+use crate::medium::*;
+use crate::phase::*;
+use crate::principled::*;
+use crate::sdf::*;
 use crate::shared_tools::*;
+use crate::spectrum::*;
 use crate::texture::*;
+use crate::thin_film::*;
+use crate::voxel::*;
 use crate::Vec3;
 use raytracer_codegen::*;
+use std::f64::consts::PI;
 use std::sync::Arc;
 
 bvhnode_impl! {}
@@ -390,3 +403,1245 @@ pub fn cornell_box() -> HitTableList {
 
     world
 }
+
+pub fn sdf_scene() -> HitTableList {
+    let mut world = HitTableList::default();
+
+    let checker = Arc::new(CheckerTexture::new(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::from(checker as Arc<dyn Texture>)),
+    )));
+
+    // a rounded box melting into a capsule
+    let blob = Arc::new(SdfSmoothUnion::new(
+        Arc::new(SdfRoundBox::new(Vec3::new(0.6, 0.6, 0.6), 0.1)),
+        Arc::new(SdfCapsule::new(
+            Vec3::new(-0.2, 0.8, 0.0),
+            Vec3::new(0.6, 1.4, 0.0),
+            0.3,
+        )),
+        0.3,
+    ));
+    world.add(Arc::new(SdfHittable::new(
+        Arc::new(SdfTranslate::new(blob, Vec3::new(-3.0, 0.7, 0.0))),
+        AABB::new(Vec3::new(-3.8, 0.0, -0.8), Vec3::new(-2.0, 2.5, 0.8)),
+        Arc::new(Lambertian::new(Vec3::new(0.8, 0.3, 0.2))),
+    )));
+
+    // a twisted column
+    let column = Arc::new(SdfTwist::new(
+        Arc::new(SdfRoundBox::new(Vec3::new(0.35, 1.2, 0.35), 0.05)),
+        1.5,
+    ));
+    let mut column = SdfHittable::new(
+        Arc::new(SdfTranslate::new(column, Vec3::new(0.0, 1.25, 0.0))),
+        AABB::new(Vec3::new(-0.6, 0.0, -0.6), Vec3::new(0.6, 2.6, 0.6)),
+        Arc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0)),
+    );
+    column.step_scale = 0.5;
+    world.add(Arc::new(column));
+
+    let mut bulb = SdfHittable::new(
+        Arc::new(SdfTranslate::new(
+            Arc::new(Mandelbulb::new(8.0, 12)),
+            Vec3::new(3.0, 1.2, 0.0),
+        )),
+        AABB::new(Vec3::new(1.8, 0.0, -1.2), Vec3::new(4.2, 2.4, 1.2)),
+        Arc::new(Lambertian::new(Vec3::new(0.3, 0.5, 0.8))),
+    );
+    bulb.step_scale = 0.8;
+    world.add(Arc::new(bulb));
+
+    // a field of spheres repeated along the x axis
+    let spheres = Arc::new(SdfRepeat::new(
+        Arc::new(SdfSphere::new(Vec3::zero(), 0.2)),
+        Vec3::new(1.0, 0.0, 0.0),
+    ));
+    world.add(Arc::new(SdfHittable::new(
+        Arc::new(SdfTranslate::new(spheres, Vec3::new(0.0, 0.2, 2.5))),
+        AABB::new(Vec3::new(-5.2, 0.0, 2.3), Vec3::new(5.2, 0.4, 2.7)),
+        Arc::new(Dielectric::new(1.5)),
+    )));
+
+    world
+}
+
+pub fn terrain() -> HitTableList {
+    let mut world = HitTableList::default();
+
+    // procedural hills from Perlin turbulence
+    let noise = Perlin::new();
+    let hills = Heightfield::new_from_fn(
+        256,
+        256,
+        |u, v| noise.turb(&Vec3::new(u * 4.0, 0.0, v * 4.0), 7),
+        Vec3::new(-10.0, -1.0, -10.0),
+        Vec3::new(20.0, 3.0, 10.0),
+        Arc::new(Lambertian::new(Vec3::new(0.35, 0.5, 0.25))),
+    );
+    world.add(Arc::new(hills));
+
+    // the earth map doubles as heights and colors
+    let earth_texture = Arc::new(ImageTexture::new("input/earthmap.jpg"));
+    let earth = Heightfield::new_from_image(
+        "input/earthmap.jpg",
+        Vec3::new(-4.0, 1.0, 0.0),
+        Vec3::new(8.0, 0.5, 4.0),
+        Arc::new(Lambertian::new_from_texture(earth_texture)),
+    );
+    world.add(Arc::new(earth));
+
+    world
+}
+
+pub fn voxel_world() -> HitTableList {
+    let mut world = HitTableList::default();
+
+    let palette: Vec<Arc<dyn Material>> = vec![
+        Arc::new(Lambertian::new(Vec3::zero())), // 0: empty
+        Arc::new(Lambertian::new(Vec3::new(0.3, 0.6, 0.2))), // 1: grass
+        Arc::new(Lambertian::new(Vec3::new(0.45, 0.3, 0.2))), // 2: dirt
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))), // 3: stone
+        Arc::new(Dielectric::new(1.5)),          // 4: glass
+    ];
+    let n = 96;
+    let mut grid = VoxelGrid::new_sparse(
+        n,
+        n / 2,
+        n,
+        Vec3::new(-6.0, -1.0, -6.0),
+        12.0 / n as f64,
+        palette,
+    );
+
+    // blocky hills
+    let noise = Perlin::new();
+    for x in 0..n {
+        for z in 0..n {
+            let p = Vec3::new(x as f64, 0.0, z as f64) * (3.0 / n as f64);
+            let h = (noise.turb(&p, 5) * 30.0) as usize + 2;
+            for y in 0..h.min(n / 2) {
+                let value = if y + 1 == h {
+                    1
+                } else if y + 4 >= h {
+                    2
+                } else {
+                    3
+                };
+                grid.set(x, y, z, value);
+            }
+        }
+    }
+    // a floating glass cube
+    for x in 40..56 {
+        for y in 34..46 {
+            for z in 40..56 {
+                grid.set(x, y, z, 4);
+            }
+        }
+    }
+    world.add(Arc::new(grid));
+
+    world
+}
+
+pub fn fur_and_grass() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.4, 0.3, 0.2))),
+    )));
+
+    // a ball of fur: thick tubes growing out of the surface
+    let fur = Arc::new(Lambertian::new(Vec3::new(0.8, 0.6, 0.4)));
+    let center = Vec3::new(-1.3, 1.0, 0.0);
+    let radius = 0.7;
+    let mut hairs = vec![];
+    for _ in 0..4000 {
+        let n = Vec3::random_unit_vector();
+        let root = center + n * radius;
+        let bend = Vec3::rand(-0.1, 0.1) + Vec3::new(0.0, -0.1, 0.0);
+        let cp = [
+            root,
+            root + n * 0.1,
+            root + n * 0.2 + bend,
+            root + n * 0.3 + bend * 2.0,
+        ];
+        hairs.push(Curve::new(
+            cp,
+            0.012,
+            0.002,
+            CurveMode::Cylinder,
+            fur.clone(),
+        ));
+    }
+    world.add(Arc::new(CurveSet::new(hairs)));
+
+    // a tuft of grass: camera-facing ribbons
+    let grass = Arc::new(Lambertian::new(Vec3::new(0.3, 0.6, 0.2)));
+    let mut blades = vec![];
+    for _ in 0..3000 {
+        let root = Vec3::new(random_f64(0.2, 2.6), 0.0, random_f64(-1.2, 1.2));
+        let height = random_f64(0.6, 1.2);
+        let lean = Vec3::new(random_f64(-0.3, 0.3), 0.0, random_f64(-0.3, 0.3));
+        let cp = [
+            root,
+            root + Vec3::new(0.0, height * 0.4, 0.0),
+            root + Vec3::new(0.0, height * 0.8, 0.0) + lean * 0.5,
+            root + Vec3::new(0.0, height, 0.0) + lean,
+        ];
+        blades.push(Curve::new(cp, 0.03, 0.0, CurveMode::Ribbon, grass.clone()));
+    }
+    world.add(Arc::new(CurveSet::new(blades)));
+
+    world
+}
+
+pub fn clouds() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+
+    // a cloud from Perlin turbulence
+    let boundary = Arc::new(Sphere::new(
+        Vec3::new(-2.0, 2.0, 0.0),
+        1.6,
+        Arc::new(Dielectric::new(1.0)),
+    ));
+    world.add(Arc::new(HeterogeneousMedium::new(
+        boundary,
+        Arc::new(PerlinDensity::new(1.5, 3.0)),
+        Arc::new(Isotropic::new_from_color(Vec3::new(0.9, 0.9, 0.9))),
+    )));
+
+    // a smoke ring sampled into a density grid
+    let n = 64;
+    let mut data = Vec::with_capacity(n * n * n);
+    for k in 0..n {
+        for j in 0..n {
+            for i in 0..n {
+                let p = Vec3::new(i as f64, j as f64, k as f64) / (n - 1) as f64 * 2.0 - 1.0;
+                let ring = ((p.x * p.x + p.y * p.y).sqrt() - 0.6).hypot(p.z);
+                data.push((1.0 - ring / 0.3).max(0.0) * 8.0);
+            }
+        }
+    }
+    let grid = GridDensity::new(
+        n,
+        n,
+        n,
+        Vec3::new(0.5, 0.5, -1.5),
+        Vec3::new(3.5, 3.5, 1.5),
+        data,
+    );
+    let boundary = Arc::new(Box::new(
+        Vec3::new(0.5, 0.5, -1.5),
+        Vec3::new(3.5, 3.5, 1.5),
+        Arc::new(Dielectric::new(1.0)),
+    ));
+    world.add(Arc::new(HeterogeneousMedium::new(
+        boundary,
+        Arc::new(grid),
+        Arc::new(Isotropic::new_from_color(Vec3::new(0.4, 0.4, 0.4))),
+    )));
+
+    world
+}
+
+// the same fog ball with four phase functions, lit from behind and from the front
+pub fn phase_functions() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.add(Arc::new(XYRect::new(
+        -6.0,
+        6.0,
+        0.5,
+        2.0,
+        -3.0,
+        Arc::new(DiffuseLight::new(Vec3::new(1.0, 0.9, 0.8), 4.0)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 6.0, 6.0),
+        1.0,
+        Arc::new(DiffuseLight::new(Vec3::new(0.8, 0.9, 1.0), 6.0)),
+    )));
+
+    let phases: Vec<Arc<dyn PhaseFunction>> = vec![
+        Arc::new(IsotropicPhase::new()),
+        Arc::new(HenyeyGreenstein::new(0.7)),
+        Arc::new(HenyeyGreenstein::new(-0.5)),
+        Arc::new(DoubleHenyeyGreenstein::new(0.8, -0.3, 0.7)),
+    ];
+    for (i, phase) in phases.into_iter().enumerate() {
+        let boundary = Arc::new(Sphere::new(
+            Vec3::new(-4.5 + 3.0 * i as f64, 1.2, 0.0),
+            1.1,
+            Arc::new(Dielectric::new(1.0)),
+        ));
+        world.add(Arc::new(ConstantMedium::new(
+            boundary,
+            2.0,
+            Arc::new(Isotropic::new_with_phase(Vec3::new(0.9, 0.9, 0.9), phase)),
+        )));
+    }
+
+    world
+}
+
+// glowing fire, blue-scattering smoke and a glass of tinted liquid
+pub fn fire_and_smoke() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -2.0,
+        2.0,
+        -1.0,
+        3.0,
+        6.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 5.0)),
+    )));
+
+    let fire = Arc::new(Sphere::new(
+        Vec3::new(-3.0, 1.2, 0.0),
+        1.2,
+        Arc::new(Dielectric::new(1.0)),
+    ));
+    world.add(Arc::new(ChromaticMedium::new(
+        fire,
+        Vec3::new(1.5, 1.5, 1.5),
+        Vec3::new(0.3, 0.3, 0.3),
+        Vec3::new(4.0, 1.2, 0.2),
+        Arc::new(HenyeyGreenstein::new(0.4)),
+    )));
+
+    let smoke = Arc::new(Sphere::new(
+        Vec3::new(0.0, 1.2, 0.0),
+        1.2,
+        Arc::new(Dielectric::new(1.0)),
+    ));
+    world.add(Arc::new(ChromaticMedium::new(
+        smoke,
+        Vec3::new(0.05, 0.05, 0.05),
+        Vec3::new(0.4, 1.0, 2.5),
+        Vec3::zero(),
+        Arc::new(Rayleigh::new()),
+    )));
+
+    // a medium boundary is no surface, the liquid absorbs inside its dielectric instead
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(3.0, 1.2, 0.0),
+        1.2,
+        Arc::new(Dielectric::new_nested(1.33, Vec3::new(0.1, 0.6, 1.5), 0)),
+    )));
+
+    world
+}
+
+// a glass of water with an ice cube, built from overlapping boxes:
+// ice > air above the water > glass > water
+pub fn glass_of_water() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.7, 0.6, 0.5))),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -3.0,
+        3.0,
+        8.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 4.0)),
+    )));
+
+    let glass = Arc::new(Dielectric::new_nested(1.5, Vec3::new(0.1, 0.02, 0.08), 2));
+    let water = Arc::new(Dielectric::new_nested(1.33, Vec3::new(0.6, 0.15, 0.05), 1));
+    let air = Arc::new(Dielectric::new_nested(1.0, Vec3::zero(), 3));
+    let ice = Arc::new(Dielectric::new_nested(1.31, Vec3::zero(), 4));
+
+    // the glass: 0.1 thick walls and a 0.2 thick bottom
+    world.add(Arc::new(Box::new(
+        Vec3::new(-1.0, 0.0, -1.0),
+        Vec3::new(1.0, 2.5, 1.0),
+        glass,
+    )));
+    // the water reaches into the walls, where the glass takes over
+    world.add(Arc::new(Box::new(
+        Vec3::new(-0.95, 0.15, -0.95),
+        Vec3::new(0.95, 1.65, 0.95),
+        water,
+    )));
+    // the cavity above the water line, open at the top
+    world.add(Arc::new(Box::new(
+        Vec3::new(-0.9, 1.6, -0.9),
+        Vec3::new(0.9, 2.7, 0.9),
+        air,
+    )));
+    let cube = Arc::new(Box::new(
+        Vec3::new(-0.35, -0.35, -0.35),
+        Vec3::new(0.35, 0.35, 0.35),
+        ice,
+    ));
+    let cube = Arc::new(RotateY::new(cube, 30.0));
+    world.add(Arc::new(Translate::new(cube, Vec3::new(0.1, 1.7, 0.0))));
+
+    world
+}
+
+// rainbows from a BK7 prism, a fused silica ball and a diamond ball
+pub fn dispersion() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.8, 0.8, 0.8))),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-6.0, 6.0, -4.0),
+        0.8,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 40.0)),
+    )));
+
+    // the prism lies on one of its faces
+    let prism = Arc::new(SdfTriPrism::new(1.5, 1.5));
+    world.add(Arc::new(Translate::new(
+        Arc::new(SdfHittable::new(
+            prism,
+            AABB::new(Vec3::new(-1.0, -0.8, -1.6), Vec3::new(1.0, 1.6, 1.6)),
+            Arc::new(Dielectric::new_dispersive(Dispersion::BK7)),
+        )),
+        Vec3::new(-2.5, 0.75, 0.0),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.5, 0.9, 0.0),
+        0.9,
+        Arc::new(Dielectric::new_dispersive(Dispersion::FUSED_SILICA)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(3.0, 0.9, 0.0),
+        0.9,
+        Arc::new(Dielectric::new_dispersive(Dispersion::DIAMOND)),
+    )));
+
+    world
+}
+
+// the same objects under a 2700K blackbody, D65 and a tri-band fluorescent lamp
+// meant for the spectral mode, where narrow-band light changes the colors of things
+pub fn spectral_lights() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.7, 0.7, 0.7))),
+    )));
+    let illuminants = [
+        Illuminant::Blackbody(2700.0),
+        Illuminant::D65,
+        Illuminant::F11,
+    ];
+    for (i, illuminant) in illuminants.iter().enumerate() {
+        let x = -4.0 + 4.0 * i as f64;
+        world.add(Arc::new(XZRect::new(
+            x - 0.8,
+            x + 0.8,
+            -0.8,
+            0.8,
+            4.0,
+            Arc::new(SpectralLight::new(*illuminant, 6.0)),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(x - 0.7, 0.6, 0.0),
+            0.6,
+            Arc::new(Lambertian::new(Vec3::new(0.8, 0.1, 0.1))),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(x + 0.7, 0.6, 0.0),
+            0.6,
+            Arc::new(Lambertian::new(Vec3::new(0.1, 0.6, 0.2))),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(x, 0.5, 1.1),
+            0.5,
+            Arc::new(Dielectric::new_dispersive(Dispersion::DIAMOND)),
+        )));
+    }
+    world
+}
+
+// GGX metals: gold, silver, copper and aluminium getting rougher from back to front,
+// and a brushed (anisotropic) sphere
+pub fn metals() -> HitTableList {
+    let mut world = HitTableList::default();
+    let checker = Arc::new(CheckerTexture::new(
+        Vec3::new(0.2, 0.2, 0.2),
+        Vec3::new(0.8, 0.8, 0.8),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new_from_texture(checker)),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -1.0,
+        1.0,
+        6.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 6.0)),
+    )));
+
+    for (row, roughness) in [0.05, 0.3].iter().enumerate() {
+        let z = -1.2 + 2.4 * row as f64;
+        let metals = [
+            RoughConductor::gold(*roughness),
+            RoughConductor::silver(*roughness),
+            RoughConductor::copper(*roughness),
+            RoughConductor::aluminium(*roughness),
+        ];
+        for (i, metal) in metals.iter().enumerate() {
+            world.add(Arc::new(Sphere::new(
+                Vec3::new(-3.3 + 2.2 * i as f64, 0.8, z),
+                0.8,
+                Arc::new(RoughConductor::new_anisotropic(
+                    metal.eta,
+                    metal.k,
+                    metal.roughness_u,
+                    metal.roughness_v,
+                )),
+            )));
+        }
+    }
+    let brushed = RoughConductor::aluminium(0.0);
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(5.2, 0.8, 0.0),
+        0.8,
+        Arc::new(RoughConductor::new_anisotropic(
+            brushed.eta,
+            brushed.k,
+            0.6,
+            0.08,
+        )),
+    )));
+
+    world
+}
+
+pub fn frosted_glass() -> HitTableList {
+    let mut world = HitTableList::default();
+    let checker = Arc::new(CheckerTexture::new(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new_from_texture(checker)),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -2.0,
+        2.0,
+        -4.0,
+        -2.0,
+        6.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 8.0)),
+    )));
+
+    for (i, roughness) in [0.05, 0.2, 0.5].iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-3.3 + 2.2 * i as f64, 1.0, 0.0),
+            1.0,
+            Arc::new(RoughDielectric::new(1.5, *roughness)),
+        )));
+    }
+    // roughness from a texture: polished and frosted patches
+    let patches = Arc::new(CheckerTexture::new(
+        Vec3::new(0.02, 0.0, 0.0),
+        Vec3::new(0.4, 0.0, 0.0),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(3.3, 1.0, 0.0),
+        1.0,
+        Arc::new(RoughDielectric::new_from_texture(1.5, patches)),
+    )));
+
+    // the same frosted shell bounds the fog inside it
+    let shell: Arc<dyn Hittable> = Arc::new(Sphere::new(
+        Vec3::new(0.0, 0.8, 2.4),
+        0.8,
+        Arc::new(RoughDielectric::new(1.5, 0.3)),
+    ));
+    world.add(shell.clone());
+    world.add(Arc::new(ConstantMedium::new(
+        shell,
+        1.5,
+        Arc::new(Isotropic::new_from_color(Vec3::new(0.9, 0.5, 0.3))),
+    )));
+
+    world
+}
+
+pub fn principled() -> HitTableList {
+    let mut world = HitTableList::default();
+    let checker = Arc::new(CheckerTexture::new(
+        Vec3::new(0.2, 0.2, 0.2),
+        Vec3::new(0.8, 0.8, 0.8),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new_from_texture(checker)),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -1.0,
+        1.0,
+        6.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 6.0)),
+    )));
+
+    // back: a metal from polished to rough
+    for i in 0..5 {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-4.4 + 2.2 * i as f64, 0.8, -1.2),
+            0.8,
+            Arc::new(Principled::new(
+                Vec3::new(0.9, 0.6, 0.2),
+                1.0,
+                0.1 + 0.2 * i as f64,
+            )),
+        )));
+    }
+
+    // front: plastic, velvet, car paint, glass and a textured plastic
+    let plastic = Principled::new(Vec3::new(0.1, 0.3, 0.8), 0.0, 0.4);
+    let mut velvet = Principled::new(Vec3::new(0.3, 0.05, 0.3), 0.0, 0.9);
+    velvet.sheen = constant(1.0);
+    let mut paint = Principled::new(Vec3::new(0.7, 0.05, 0.05), 0.5, 0.5);
+    paint.clearcoat = constant(1.0);
+    let mut glass = Principled::new(Vec3::ones(), 0.0, 0.1);
+    glass.transmission = constant(1.0);
+    let mut textured = Principled::new_from_texture(
+        Arc::new(NoiseTexture::new(4.0)),
+        constant(0.0),
+        Arc::new(CheckerTexture::new(
+            Vec3::new(0.05, 0.0, 0.0),
+            Vec3::new(0.6, 0.0, 0.0),
+        )),
+    );
+    textured.specular = constant(1.0);
+    let front = vec![plastic, velvet, paint, glass, textured];
+    for (i, mat) in front.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-4.4 + 2.2 * i as f64, 0.8, 1.2),
+            0.8,
+            Arc::new(mat),
+        )));
+    }
+
+    // an emissive one in between
+    let mut glow = Principled::new(Vec3::new(0.8, 0.8, 0.8), 0.0, 0.5);
+    glow.emission = Arc::new(SolidColor::new(Vec3::new(4.0, 2.0, 0.5)));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 0.3, 3.0),
+        0.3,
+        Arc::new(glow),
+    )));
+
+    world
+}
+
+pub fn layered_materials() -> HitTableList {
+    let mut world = HitTableList::default();
+    let checker = Arc::new(CheckerTexture::new(
+        Vec3::new(0.2, 0.2, 0.2),
+        Vec3::new(0.8, 0.8, 0.8),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new_from_texture(checker.clone())),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -1.0,
+        1.0,
+        6.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 6.0)),
+    )));
+
+    // rust on metal, the mask from noise
+    let rusty = MixMaterial::new_from_texture(
+        Arc::new(RoughConductor::aluminium(0.2)),
+        Arc::new(Lambertian::new(Vec3::new(0.45, 0.18, 0.06))),
+        Arc::new(NoiseTexture::new(3.0)),
+    );
+    // half gold, half silver
+    let alloy = MixMaterial::new(
+        Arc::new(RoughConductor::gold(0.15)),
+        Arc::new(RoughConductor::silver(0.15)),
+        0.5,
+    );
+    // varnished paint and a varnished checker
+    let paint = CoatedMaterial::new(Arc::new(Lambertian::new(Vec3::new(0.7, 0.1, 0.1))), 1.5);
+    let tiles = CoatedMaterial::new(Arc::new(Lambertian::new_from_texture(checker)), 1.5);
+    // an amber lacquer over polished aluminium
+    let lacquer = CoatedMaterial::new_tinted(
+        Arc::new(RoughConductor::aluminium(0.05)),
+        1.5,
+        Vec3::new(0.9, 0.6, 0.2),
+        0.1,
+    );
+    let materials: Vec<Arc<dyn Material>> = vec![
+        Arc::new(rusty),
+        Arc::new(alloy),
+        Arc::new(paint),
+        Arc::new(tiles),
+        Arc::new(lacquer),
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-4.4 + 2.2 * i as f64, 0.8, 0.0),
+            0.8,
+            mat,
+        )));
+    }
+
+    world
+}
+
+pub fn rough_diffuse() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(OrenNayar::new(Vec3::new(0.5, 0.5, 0.5), 30.0)),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -4.0,
+        -2.0,
+        5.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 8.0)),
+    )));
+
+    // smooth to rough clay, then cloth
+    let materials: Vec<Arc<dyn Material>> = vec![
+        Arc::new(Lambertian::new(Vec3::new(0.8, 0.6, 0.4))),
+        Arc::new(OrenNayar::new(Vec3::new(0.8, 0.6, 0.4), 20.0)),
+        Arc::new(OrenNayar::new(Vec3::new(0.8, 0.6, 0.4), 60.0)),
+        Arc::new(Fabric::velvet(Vec3::new(0.7, 0.05, 0.1))),
+        Arc::new(Fabric::new(
+            Vec3::new(0.05, 0.1, 0.4),
+            Vec3::new(0.8, 0.8, 0.8),
+            0.3,
+        )),
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-4.4 + 2.2 * i as f64, 0.8, 0.0),
+            0.8,
+            mat,
+        )));
+    }
+
+    world
+}
+
+pub fn thin_films() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new_from_texture(Arc::new(CheckerTexture::new(
+            Vec3::new(0.1, 0.1, 0.1),
+            Vec3::new(0.8, 0.8, 0.8),
+        )))),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -4.0,
+        -2.0,
+        5.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 8.0)),
+    )));
+
+    // soap bubbles, water films swirling between 0 and 800nm
+    for (i, center) in [
+        Vec3::new(-3.0, 1.2, 0.0),
+        Vec3::new(-1.2, 2.2, -1.5),
+        Vec3::new(-1.0, 0.6, 1.2),
+    ]
+    .iter()
+    .enumerate()
+    {
+        world.add(Arc::new(Sphere::new(
+            *center,
+            0.6 + 0.2 * i as f64,
+            Arc::new(Dielectric::new_thin_film(
+                1.0,
+                ThinFilm::new_from_texture(
+                    Arc::new(NoiseTexture::new(2.0 + i as f64)),
+                    800.0,
+                    1.33,
+                ),
+            )),
+        )));
+    }
+    // anodized titanium: thicker oxide, later colors
+    for (i, thickness) in [60.0, 110.0, 170.0].iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(1.2 + 1.7 * i as f64, 0.8, 0.0),
+            0.8,
+            Arc::new(RoughConductor::new_thin_film(
+                RoughConductor::new(Vec3::new(2.7, 2.5, 2.2), Vec3::new(3.8, 3.4, 3.0), 0.15),
+                ThinFilm::new(*thickness, 2.4),
+            )),
+        )));
+    }
+
+    world
+}
+
+pub fn subsurface() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.4, 0.4, 0.4))),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -4.0,
+        -2.0,
+        5.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 8.0)),
+    )));
+
+    // skin, marble, wax and jade: red light travels furthest in skin
+    let materials = vec![
+        Dielectric::new_subsurface(
+            1.4,
+            Vec3::new(0.85, 0.55, 0.45),
+            Vec3::new(0.5, 0.2, 0.1),
+            0.0,
+        ),
+        Dielectric::new_subsurface(
+            1.5,
+            Vec3::new(0.9, 0.9, 0.88),
+            Vec3::new(0.3, 0.3, 0.3),
+            0.0,
+        ),
+        Dielectric::new_subsurface(
+            1.45,
+            Vec3::new(0.9, 0.75, 0.45),
+            Vec3::new(0.6, 0.4, 0.2),
+            0.3,
+        ),
+        Dielectric::new_subsurface(
+            1.6,
+            Vec3::new(0.3, 0.75, 0.45),
+            Vec3::new(0.3, 0.8, 0.4),
+            0.0,
+        ),
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-3.3 + 2.2 * i as f64, 0.8, 0.0),
+            0.8,
+            Arc::new(mat),
+        )));
+    }
+
+    world
+}
+
+pub fn hair() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -4.0,
+        -2.0,
+        5.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 8.0)),
+    )));
+
+    // blond, brown, black, red and dyed blue balls of hair
+    let materials: Vec<Arc<dyn Material>> = vec![
+        Arc::new(Hair::new_melanin(0.3, 0.0, 0.3, 0.3)),
+        Arc::new(Hair::new_melanin(1.3, 0.0, 0.3, 0.3)),
+        Arc::new(Hair::new_melanin(8.0, 0.0, 0.3, 0.3)),
+        Arc::new(Hair::new_melanin(0.5, 2.0, 0.3, 0.3)),
+        Arc::new(Hair::new_from_color(Vec3::new(0.1, 0.3, 0.8), 0.25, 0.3)),
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        let center = Vec3::new(-4.4 + 2.2 * i as f64, 0.8, 0.0);
+        world.add(Arc::new(Sphere::new(
+            center,
+            0.5,
+            Arc::new(Lambertian::new(Vec3::new(0.05, 0.05, 0.05))),
+        )));
+        let mut hairs = vec![];
+        for _ in 0..3000 {
+            let n = Vec3::random_unit_vector();
+            let root = center + n * 0.5;
+            let comb = Vec3::new(0.0, -0.25, 0.0) + Vec3::rand(-0.05, 0.05);
+            let cp = [
+                root,
+                root + n * 0.15,
+                root + n * 0.25 + comb,
+                root + n * 0.3 + comb * 2.0,
+            ];
+            hairs.push(Curve::new(cp, 0.01, 0.004, CurveMode::Ribbon, mat.clone()));
+        }
+        world.add(Arc::new(CurveSet::new(hairs)));
+    }
+
+    world
+}
+
+// the .binary files come from the MERL BRDF database, they are not part of the repository;
+// without them, or with broken ones, the spheres show analytic stand-ins tabulated the same way
+pub fn measured() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -4.0,
+        -2.0,
+        5.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 8.0)),
+    )));
+
+    // diffuse color, Fresnel at normal incidence and width of the highlight for stand-ins
+    // with a similar look, used where the file is missing or can't be read
+    let materials = [
+        ("gold-metallic-paint", Vec3::new(0.3, 0.2, 0.05), 0.6, 0.15),
+        ("blue-acrylic", Vec3::new(0.05, 0.1, 0.5), 0.04, 0.05),
+        ("red-fabric", Vec3::new(0.5, 0.05, 0.05), 0.0, 0.5),
+        ("chrome-steel", Vec3::zero(), 0.6, 0.05),
+    ];
+    for (i, (name, diffuse, f0, alpha)) in materials.iter().enumerate() {
+        let path = format!("input/merl/{}.binary", name);
+        let brdf = MeasuredBrdf::new_from_file(&path).unwrap_or_else(|_| {
+            let (diffuse, f0, a2) = (*diffuse, *f0, alpha * alpha);
+            // Lambertian plus a GGX highlight with Schlick's Fresnel
+            MeasuredBrdf::new_from_fn(move |theta_h: f64, theta_d: f64, _| {
+                let t = theta_h.cos().powi(2) * (a2 - 1.0) + 1.0;
+                let fresnel = f0 + (1.0 - f0) * (1.0 - theta_d.cos()).powi(5);
+                diffuse / PI + Vec3::ones() * (a2 / (PI * t * t) * fresnel / 4.0)
+            })
+        });
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-3.3 + 2.2 * i as f64, 0.8, 0.0),
+            0.8,
+            Arc::new(brdf),
+        )));
+    }
+
+    world
+}
+
+pub fn bump_mapping() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -4.0,
+        -2.0,
+        5.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 8.0)),
+    )));
+
+    // plaster, hammered copper and rippled glass from the same noise
+    let noise = Arc::new(NoiseTexture::new(4.0));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-3.3, 0.8, 0.0),
+        0.8,
+        Arc::new(BumpMapped::new(
+            Arc::new(Lambertian::new(Vec3::new(0.8, 0.75, 0.65))),
+            noise.clone(),
+            0.02,
+        )),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-1.1, 0.8, 0.0),
+        0.8,
+        Arc::new(BumpMapped::new(
+            Arc::new(RoughConductor::copper(0.15)),
+            noise.clone(),
+            0.03,
+        )),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(1.1, 0.8, 0.0),
+        0.8,
+        Arc::new(BumpMapped::new(Arc::new(Dielectric::new(1.5)), noise, 0.01)),
+    )));
+    // a normal map of tiles tilted left and right
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(3.3, 0.8, 0.0),
+        0.8,
+        Arc::new(NormalMapped::new(
+            Arc::new(Metal::new(Vec3::new(0.8, 0.8, 0.85), 0.05)),
+            Arc::new(CheckerTexture::new(
+                Vec3::new(0.8, 0.5, 0.9),
+                Vec3::new(0.2, 0.5, 0.9),
+            )),
+        )),
+    )));
+
+    world
+}
+
+pub fn cutouts() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -4.0,
+        -2.0,
+        5.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 8.0)),
+    )));
+
+    // a lattice fence behind, holes where the checker is dark
+    world.add(Arc::new(XYRect::new(
+        -5.0,
+        5.0,
+        0.0,
+        2.5,
+        -1.5,
+        Arc::new(Cutout::new(
+            Arc::new(Lambertian::new(Vec3::new(0.55, 0.35, 0.2))),
+            Arc::new(CheckerTexture::new(Vec3::zero(), Vec3::ones())),
+        )),
+    )));
+    // lace from thresholded noise, seen through from both sides
+    world.add(Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(XYRect::new(
+                -1.0,
+                1.0,
+                0.0,
+                2.0,
+                0.0,
+                Arc::new(Cutout::new(
+                    Arc::new(Lambertian::new(Vec3::new(0.9, 0.9, 0.85))),
+                    Arc::new(NoiseTexture::new(8.0)),
+                )),
+            )),
+            30.0,
+        )),
+        Vec3::new(-2.2, 0.0, 1.0),
+    )));
+    // a ghost: each hit stands with the chance of its opacity
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.5, 0.8, 0.5),
+        0.8,
+        Arc::new(Cutout::new_stochastic(
+            Arc::new(Lambertian::new(Vec3::new(0.8, 0.2, 0.2))),
+            Arc::new(SolidColor::new_from_f64(0.35, 0.0, 0.0)),
+        )),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(2.6, 0.8, 0.0),
+        0.8,
+        Arc::new(Cutout::new_stochastic(
+            Arc::new(Metal::new(Vec3::new(0.8, 0.8, 0.85), 0.0)),
+            Arc::new(NoiseTexture::new(4.0)),
+        )),
+    )));
+
+    world
+}
+
+pub fn texture_mapping() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -4.0,
+        -2.0,
+        5.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 8.0)),
+    )));
+
+    // tiles: repeated, nearest texels
+    let mut tiles = ImageTexture::new("input/earthmap.jpg");
+    tiles.wrap = WrapMode::Repeat;
+    tiles.filter = TextureFilter::Nearest;
+    tiles.transform = UvTransform::new((3.0, 3.0), (0.0, 0.0), 0.0);
+    world.add(Arc::new(XYRect::new(
+        -4.2,
+        -1.8,
+        0.0,
+        2.4,
+        -1.0,
+        Arc::new(Lambertian::new_from_texture(Arc::new(tiles))),
+    )));
+    // mirrored and turned, bilinear
+    let mut mirrored = ImageTexture::new("input/earthmap.jpg");
+    mirrored.wrap = WrapMode::Mirror;
+    mirrored.transform = UvTransform::new((2.0, 2.0), (0.25, 0.0), 30.0);
+    world.add(Arc::new(XYRect::new(
+        -1.2,
+        1.2,
+        0.0,
+        2.4,
+        -1.0,
+        Arc::new(Lambertian::new_from_texture(Arc::new(mirrored))),
+    )));
+    // the globe, bicubic
+    let mut earth = ImageTexture::new("input/earthmap.jpg");
+    earth.wrap = WrapMode::Repeat;
+    earth.filter = TextureFilter::Bicubic;
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(3.0, 1.2, -0.2),
+        1.2,
+        Arc::new(Lambertian::new_from_texture(Arc::new(earth))),
+    )));
+
+    world
+}
+
+pub fn texture_filtering() -> HitTableList {
+    let mut world = HitTableList::default();
+    // receding tiles on the left, filtered over the footprint of each sample
+    let mut tiles = ImageTexture::new("input/earthmap.jpg");
+    tiles.wrap = WrapMode::Repeat;
+    tiles.mip = MipFilter::Ewa;
+    tiles.transform = UvTransform::new((30.0, 30.0), (0.0, 0.0), 0.0);
+    world.add(Arc::new(XZRect::new(
+        -60.0,
+        0.0,
+        -80.0,
+        20.0,
+        0.0,
+        Arc::new(Lambertian::new_from_texture(Arc::new(tiles))),
+    )));
+    // a checkerboard on the right, fading to gray where the checks get too small
+    world.add(Arc::new(XZRect::new(
+        0.0,
+        60.0,
+        -80.0,
+        20.0,
+        0.05,
+        Arc::new(Lambertian::new_from_texture(Arc::new(CheckerTexture::new(
+            Vec3::new(0.1, 0.1, 0.1),
+            Vec3::new(0.9, 0.9, 0.9),
+        )))),
+    )));
+    // the footprint follows the ray differentials through a mirror
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 1.0, 0.0),
+        1.0,
+        Arc::new(Metal::new(Vec3::new(0.9, 0.9, 0.9), 0.0)),
+    )));
+    world.add(Arc::new(XZRect::new(
+        -3.0,
+        3.0,
+        -4.0,
+        -2.0,
+        5.0,
+        Arc::new(DiffuseLight::new(Vec3::ones(), 8.0)),
+    )));
+
+    world
+}
+
+pub fn hdr_textures() -> HitTableList {
+    let mut world = HitTableList::default();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    // an environment map: the sky around everything, the sun far brighter than 1
+    let mut sky = ImageTexture::new("input/sky.hdr");
+    sky.wrap = WrapMode::Repeat;
+    world.add(Arc::new(Sphere::new(
+        Vec3::zero(),
+        100.0,
+        Arc::new(DiffuseLight::new_from_texture(Arc::new(sky), 1.0)),
+    )));
+    // a sign lit from within, from floats
+    world.add(Arc::new(XYRect::new(
+        -3.0,
+        3.0,
+        0.5,
+        2.5,
+        -2.0,
+        Arc::new(DiffuseLight::new_from_texture(
+            Arc::new(ImageTexture::new("input/neon.exr")),
+            1.0,
+        )),
+    )));
+
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-2.2, 0.8, 0.5),
+        0.8,
+        Arc::new(Metal::new(Vec3::new(0.9, 0.9, 0.9), 0.0)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 0.8, 0.5),
+        0.8,
+        Arc::new(Dielectric::new(1.5)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(2.2, 0.8, 0.5),
+        0.8,
+        Arc::new(Lambertian::new(Vec3::new(0.8, 0.8, 0.8))),
+    )));
+
+    world
+}
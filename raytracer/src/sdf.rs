@@ -0,0 +1,441 @@
+// Signed distance fields, rendered by sphere tracing
+// An SDF returns the distance from p to the nearest surface: positive outside, negative inside
+
+use crate::bvh::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::Ray;
+use crate::shared_tools::*;
+use crate::texture::Footprint;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+pub trait SDF: Send + Sync {
+    fn distance(&self, p: Vec3) -> f64;
+    // analytic gradient; None falls back on central differences
+    fn gradient(&self, _p: Vec3) -> Option<Vec3> {
+        None
+    }
+}
+
+fn vabs(p: Vec3) -> Vec3 {
+    Vec3::new(p.x.abs(), p.y.abs(), p.z.abs())
+}
+
+fn vmax(p: Vec3, k: f64) -> Vec3 {
+    Vec3::new(p.x.max(k), p.y.max(k), p.z.max(k))
+}
+
+fn mix(a: f64, b: f64, t: f64) -> f64 {
+    a * (1.0 - t) + b * t
+}
+
+pub struct SdfHittable {
+    pub sdf: Arc<dyn SDF>,
+    pub bbox: AABB, // conservative bound, used for the BVH and to clip the march
+    pub mat_ptr: Arc<dyn Material>,
+    pub tolerance: f64,
+    pub max_steps: u32,
+    // < 1.0 for fields that overestimate the distance (twist, fractals)
+    pub step_scale: f64,
+    pub normal_eps: f64,
+}
+impl Hittable for SdfHittable {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t_enter, t_exit) = self.bbox.hit_range(r, t_min, t_max)?;
+        let ray_length = r.dir.length();
+        let mut t = t_enter;
+        // rays that start inside (refraction) march on the negative side
+        let mut side = if self.sdf.distance(r.at(t)) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        // through a surface whose material let the ray pass, until clear of it
+        let mut crossing = false;
+
+        for _i in 0..self.max_steps {
+            let d = side * self.sdf.distance(r.at(t));
+            if crossing {
+                if d < self.tolerance {
+                    t += self.tolerance / ray_length;
+                    if t > t_exit {
+                        return None;
+                    }
+                    continue;
+                }
+                crossing = false;
+            }
+            if d < self.tolerance {
+                let p = r.at(t);
+                let outward_normal = self.normal(p);
+                let mut ret = HitRecord {
+                    t,
+                    p,
+                    normal: outward_normal,
+                    front_face: false,
+                    tangent: Vec3::zero(),
+                    dpdu: Vec3::zero(),
+                    dpdv: Vec3::zero(),
+                    dndu: Vec3::zero(),
+                    dndv: Vec3::zero(),
+                    footprint: Footprint::point(),
+                    mat_ptr: self.mat_ptr.clone(),
+                    // an SDF has no natural parametrization
+                    u: 0.0,
+                    v: 0.0,
+                };
+                ret.set_face_normal(r, &outward_normal);
+                if ret.mat_ptr.opaque(&ret) {
+                    return Some(ret);
+                }
+                // march on from the other side
+                side = -side;
+                crossing = true;
+                continue;
+            }
+            t += d * self.step_scale / ray_length;
+            if t > t_exit {
+                return None;
+            }
+        }
+        None
+    }
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.bbox.clone())
+    }
+}
+impl SdfHittable {
+    pub fn new(sdf: Arc<dyn SDF>, bbox: AABB, mat_ptr: Arc<dyn Material>) -> Self {
+        Self::new_with_limits(sdf, bbox, 1e-4, 256, mat_ptr)
+    }
+
+    pub fn new_with_limits(
+        sdf: Arc<dyn SDF>,
+        bbox: AABB,
+        tolerance: f64,
+        max_steps: u32,
+        mat_ptr: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            sdf,
+            bbox,
+            mat_ptr,
+            tolerance,
+            max_steps,
+            step_scale: 1.0,
+            normal_eps: tolerance,
+        }
+    }
+
+    pub fn normal(&self, p: Vec3) -> Vec3 {
+        if let Some(grad) = self.sdf.gradient(p) {
+            return grad.unit();
+        }
+        // central differences
+        let e = self.normal_eps;
+        let dx = Vec3::new(e, 0.0, 0.0);
+        let dy = Vec3::new(0.0, e, 0.0);
+        let dz = Vec3::new(0.0, 0.0, e);
+        Vec3::new(
+            self.sdf.distance(p + dx) - self.sdf.distance(p - dx),
+            self.sdf.distance(p + dy) - self.sdf.distance(p - dy),
+            self.sdf.distance(p + dz) - self.sdf.distance(p - dz),
+        )
+        .unit()
+    }
+}
+
+// ---------- primitives ----------
+
+pub struct SdfSphere {
+    pub center: Vec3,
+    pub radius: f64,
+}
+impl SDF for SdfSphere {
+    fn distance(&self, p: Vec3) -> f64 {
+        (p - self.center).length() - self.radius
+    }
+    fn gradient(&self, p: Vec3) -> Option<Vec3> {
+        Some(p - self.center)
+    }
+}
+impl SdfSphere {
+    pub fn new(center: Vec3, radius: f64) -> Self {
+        Self { center, radius }
+    }
+}
+
+// a box centered at the origin, with edges rounded by radius
+pub struct SdfRoundBox {
+    pub half_extents: Vec3,
+    pub radius: f64,
+}
+impl SDF for SdfRoundBox {
+    fn distance(&self, p: Vec3) -> f64 {
+        let q = vabs(p) - self.half_extents;
+        vmax(q, 0.0).length() + q.x.max(q.y.max(q.z)).min(0.0) - self.radius
+    }
+}
+impl SdfRoundBox {
+    pub fn new(half_extents: Vec3, radius: f64) -> Self {
+        Self {
+            half_extents,
+            radius,
+        }
+    }
+}
+
+// the segment a-b swept by a sphere
+pub struct SdfCapsule {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub radius: f64,
+}
+impl SDF for SdfCapsule {
+    fn distance(&self, p: Vec3) -> f64 {
+        let pa = p - self.a;
+        let ba = self.b - self.a;
+        let h = clamp((pa * ba) / (ba * ba), 0.0, 1.0);
+        (pa - ba * h).length() - self.radius
+    }
+}
+impl SdfCapsule {
+    pub fn new(a: Vec3, b: Vec3, radius: f64) -> Self {
+        Self { a, b, radius }
+    }
+}
+
+// a prism along z: an equilateral triangle of the given height in xy, half_length deep
+// (a bound rather than an exact distance, which sphere tracing tolerates)
+pub struct SdfTriPrism {
+    pub height: f64,
+    pub half_length: f64,
+}
+impl SDF for SdfTriPrism {
+    fn distance(&self, p: Vec3) -> f64 {
+        let q = vabs(p);
+        (q.z - self.half_length).max((q.x * 0.866_025 + p.y * 0.5).max(-p.y) - self.height * 0.5)
+    }
+}
+impl SdfTriPrism {
+    pub fn new(height: f64, half_length: f64) -> Self {
+        Self {
+            height,
+            half_length,
+        }
+    }
+}
+
+// ---------- operators ----------
+
+pub struct SdfTranslate {
+    pub ptr: Arc<dyn SDF>,
+    pub offset: Vec3,
+}
+impl SDF for SdfTranslate {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.ptr.distance(p - self.offset)
+    }
+    fn gradient(&self, p: Vec3) -> Option<Vec3> {
+        self.ptr.gradient(p - self.offset)
+    }
+}
+impl SdfTranslate {
+    pub fn new(ptr: Arc<dyn SDF>, offset: Vec3) -> Self {
+        Self { ptr, offset }
+    }
+}
+
+pub struct SdfUnion {
+    pub a: Arc<dyn SDF>,
+    pub b: Arc<dyn SDF>,
+}
+impl SDF for SdfUnion {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.a.distance(p).min(self.b.distance(p))
+    }
+}
+impl SdfUnion {
+    pub fn new(a: Arc<dyn SDF>, b: Arc<dyn SDF>) -> Self {
+        Self { a, b }
+    }
+}
+
+// polynomial smooth minimum, k: the blending radius
+pub struct SdfSmoothUnion {
+    pub a: Arc<dyn SDF>,
+    pub b: Arc<dyn SDF>,
+    pub k: f64,
+}
+impl SDF for SdfSmoothUnion {
+    fn distance(&self, p: Vec3) -> f64 {
+        let d1 = self.a.distance(p);
+        let d2 = self.b.distance(p);
+        let h = clamp(0.5 + 0.5 * (d2 - d1) / self.k, 0.0, 1.0);
+        mix(d2, d1, h) - self.k * h * (1.0 - h)
+    }
+}
+impl SdfSmoothUnion {
+    pub fn new(a: Arc<dyn SDF>, b: Arc<dyn SDF>, k: f64) -> Self {
+        Self { a, b, k }
+    }
+}
+
+// rotate around the y axis by k radians per unit of height
+// not a true distance any more: march it with step_scale < 1.0
+pub struct SdfTwist {
+    pub ptr: Arc<dyn SDF>,
+    pub k: f64,
+}
+impl SDF for SdfTwist {
+    fn distance(&self, p: Vec3) -> f64 {
+        let cos = (self.k * p.y).cos();
+        let sin = (self.k * p.y).sin();
+        let q = Vec3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z);
+        self.ptr.distance(q)
+    }
+}
+impl SdfTwist {
+    pub fn new(ptr: Arc<dyn SDF>, k: f64) -> Self {
+        Self { ptr, k }
+    }
+}
+
+// infinite repetition with the given period, a zero component disables that axis
+pub struct SdfRepeat {
+    pub ptr: Arc<dyn SDF>,
+    pub period: Vec3,
+}
+impl SDF for SdfRepeat {
+    fn distance(&self, p: Vec3) -> f64 {
+        let rep = |x: f64, c: f64| {
+            if c > 0.0 {
+                x - c * (x / c).round()
+            } else {
+                x
+            }
+        };
+        let q = Vec3::new(
+            rep(p.x, self.period.x),
+            rep(p.y, self.period.y),
+            rep(p.z, self.period.z),
+        );
+        self.ptr.distance(q)
+    }
+}
+impl SdfRepeat {
+    pub fn new(ptr: Arc<dyn SDF>, period: Vec3) -> Self {
+        Self { ptr, period }
+    }
+}
+
+// ---------- fractals ----------
+
+// distance estimator of the power-n Mandelbulb, fits in [-1.2, 1.2]^3 for power 8
+pub struct Mandelbulb {
+    pub power: f64,
+    pub iterations: u32,
+    pub bailout: f64,
+}
+impl SDF for Mandelbulb {
+    fn distance(&self, p: Vec3) -> f64 {
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = 0.0;
+        for _i in 0..self.iterations {
+            r = z.length();
+            if r > self.bailout {
+                break;
+            }
+            // stuck at the origin: inside the set, and no angles to take
+            if r < f64::EPSILON {
+                return 0.0;
+            }
+            // to polar coordinates
+            let theta = (z.z / r).acos();
+            let phi = z.y.atan2(z.x);
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+
+            // scale and rotate the point
+            let zr = r.powf(self.power);
+            let theta = theta * self.power;
+            let phi = phi * self.power;
+            z = Vec3::new(
+                theta.sin() * phi.cos(),
+                phi.sin() * theta.sin(),
+                theta.cos(),
+            ) * zr
+                + p;
+        }
+        if r < f64::EPSILON {
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr
+    }
+}
+impl Mandelbulb {
+    pub fn new(power: f64, iterations: u32) -> Self {
+        Self {
+            power,
+            iterations,
+            bailout: 2.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(max_steps: u32) -> SdfHittable {
+        SdfHittable::new_with_limits(
+            Arc::new(SdfSphere::new(Vec3::zero(), 1.0)),
+            AABB::new(Vec3::ones() * -2.0, Vec3::ones() * 2.0),
+            1e-4,
+            max_steps,
+            Arc::new(Lambertian::new(Vec3::ones() * 0.5)),
+        )
+    }
+
+    #[test]
+    fn sphere_hit() {
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 2.0));
+        let rec = sphere(256).hit(&r, 0.001, f64::INFINITY).unwrap();
+        // the ray direction is not normalized, t is in its units
+        assert!((rec.t - 2.0).abs() < 1e-4);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-3);
+        assert!(rec.front_face);
+
+        // off center, a grazing distance from the surface
+        let r = Ray::new(Vec3::new(0.6, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let rec = sphere(256).hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - (5.0 - 0.8)).abs() < 1e-4);
+        assert!((rec.normal - Vec3::new(0.6, 0.0, -0.8)).length() < 1e-3);
+
+        // t_max before the surface
+        assert!(sphere(256).hit(&r, 0.001, 4.0).is_none());
+    }
+
+    #[test]
+    fn sphere_miss() {
+        // through the bounding box, past the sphere
+        let r = Ray::new(Vec3::new(1.2, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(sphere(256).hit(&r, 0.001, f64::INFINITY).is_none());
+        // away from it
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(sphere(256).hit(&r, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn max_steps() {
+        // from the box at z = -2 the first step lands on the surface, the second one sees it
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(sphere(1).hit(&r, 0.001, f64::INFINITY).is_none());
+        assert!(sphere(2).hit(&r, 0.001, f64::INFINITY).is_some());
+        // a ray just inside the tolerance of a tangent takes ever shorter steps
+        let r = Ray::new(Vec3::new(1.0 + 5e-5, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(sphere(16).hit(&r, 0.001, f64::INFINITY).is_none());
+        assert!(sphere(4096).hit(&r, 0.001, f64::INFINITY).is_some());
+    }
+}
@@ -1,12 +1,46 @@
 use image::GenericImageView;
 use std::path::Path;
 
+use crate::hdr::*;
 use crate::shared_tools::*;
 use crate::vec3::Vec3;
+use std::f64::consts::PI;
 use std::sync::Arc;
 
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3;
+    // the average over the footprint of a hit around (u, v, p), see HitRecord::set_footprint;
+    // textures that don't filter point-sample
+    fn filtered(&self, u: f64, v: f64, p: Vec3, _footprint: &Footprint) -> Vec3 {
+        self.value(u, v, p)
+    }
+}
+
+// how much u, v and p change from a sample on the image to the next one in x and in y,
+// all zero for a point
+#[derive(Clone, Copy)]
+pub struct Footprint {
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+}
+impl Footprint {
+    pub fn point() -> Self {
+        Self {
+            dudx: 0.0,
+            dvdx: 0.0,
+            dudy: 0.0,
+            dvdy: 0.0,
+            dpdx: Vec3::zero(),
+            dpdy: Vec3::zero(),
+        }
+    }
+    pub fn is_point(&self) -> bool {
+        self.dpdx.squared_length() == 0.0 && self.dpdy.squared_length() == 0.0
+    }
 }
 
 pub struct SolidColor {
@@ -41,8 +75,22 @@ impl Texture for CheckerTexture {
             self.even.value(u, v, p)
         }
     }
+    fn filtered(&self, u: f64, v: f64, p: Vec3, footprint: &Footprint) -> Vec3 {
+        let sine = (10.0 * p.x).sin() * (10.0 * p.y).sin() * (10.0 * p.z).sin();
+        let odd = self.odd.filtered(u, v, p, footprint);
+        let even = self.even.filtered(u, v, p, footprint);
+        let check = if sine < 0.0 { odd } else { even };
+        let fade = Self::fade(footprint);
+        check * (1.0 - fade) + (odd + even) * (0.5 * fade)
+    }
 }
 impl CheckerTexture {
+    // the checks a sample covers: one wide or more is gray
+    fn fade(footprint: &Footprint) -> f64 {
+        let width = footprint.dpdx.length().max(footprint.dpdy.length()) * 10.0 / PI;
+        clamp(2.0 * width - 1.0, 0.0, 1.0)
+    }
+
     pub fn new(color1: Vec3, color2: Vec3) -> Self {
         Self {
             odd: Arc::new(SolidColor::new(color1)),
@@ -51,37 +99,374 @@ impl CheckerTexture {
     }
 }
 
-pub struct ImageTexture {
-    img: image::DynamicImage,
-    width: u32,
-    height: u32,
+// how an ImageTexture reads between texel centers
+#[derive(Clone, Copy, PartialEq)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+    Bicubic, // Catmull-Rom over 4 x 4 texels
 }
-impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, _p: Vec3) -> Vec3 {
-        let u = clamp(u, 0.0, 1.0);
-        let v = 1.0 - clamp(v, 0.0, 1.0);
 
-        let i = (u * self.width as f64) as u32;
-        let j = (v * self.height as f64) as u32;
+// what an ImageTexture shows outside [0, 1]
+#[derive(Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Mirror, // repeat, every other copy flipped
+    Clamp,  // the edge texels stretch out
+}
 
-        let i = i.min(self.width - 1);
-        let j = j.min(self.height - 1);
+// how an ImageTexture averages over the footprint of a hit, from its mip pyramid
+#[derive(Clone, Copy, PartialEq)]
+pub enum MipFilter {
+    None,      // the full image only, as value()
+    Trilinear, // between the two levels the footprint is one to two texels wide in
+    Ewa,       // an elliptical Gaussian over the footprint (Heckbert 1989, as in pbrt)
+}
 
-        let pixel = self.img.get_pixel(i, j);
-        Vec3::new(
-            pixel[0] as f64 / 255.0,
-            pixel[1] as f64 / 255.0,
-            pixel[2] as f64 / 255.0,
+// applied to (u, v) before the lookup: scaled, then turned counterclockwise by rotation
+// (degrees) about the origin, then moved by offset
+#[derive(Clone, Copy)]
+pub struct UvTransform {
+    pub scale: (f64, f64),
+    pub offset: (f64, f64),
+    pub rotation: f64,
+}
+impl UvTransform {
+    pub fn new(scale: (f64, f64), offset: (f64, f64), rotation: f64) -> Self {
+        Self {
+            scale,
+            offset,
+            rotation,
+        }
+    }
+    pub fn identity() -> Self {
+        Self::new((1.0, 1.0), (0.0, 0.0), 0.0)
+    }
+    pub fn apply(&self, u: f64, v: f64) -> (f64, f64) {
+        let (u, v) = (u * self.scale.0, v * self.scale.1);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        (
+            u * cos - v * sin + self.offset.0,
+            u * sin + v * cos + self.offset.1,
         )
     }
+    // a change of (u, v), as apply() changes it
+    pub fn apply_vector(&self, du: f64, dv: f64) -> (f64, f64) {
+        let (du, dv) = (du * self.scale.0, dv * self.scale.1);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        (du * cos - dv * sin, du * sin + dv * cos)
+    }
+}
+
+// the sRGB transfer function undone
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// the Catmull-Rom weights of the 4 texels around a point t of the way from the second to the third
+fn catmull_rom(t: f64) -> [f64; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        0.5 * (-t3 + 2.0 * t2 - t),
+        0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+        0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
+
+// the EWA ellipse is stretched to at most this many times its width, longer ones are blurred
+const MAX_ANISOTROPY: f64 = 8.0;
+
+// one level of a mip pyramid
+struct MipLevel {
+    width: usize,
+    height: usize,
+    texels: Vec<Vec3>, // linear, the top row first
+}
+impl MipLevel {
+    // half the size, each texel the mean of the 2 x 2 below it (the last row or column of an
+    // odd size counts twice)
+    fn downsample(&self) -> Self {
+        let (width, height) = (self.width - self.width / 2, self.height - self.height / 2);
+        let at = |i: usize, j: usize| {
+            self.texels[j.min(self.height - 1) * self.width + i.min(self.width - 1)]
+        };
+        let mut texels = Vec::with_capacity(width * height);
+        for j in 0..height {
+            for i in 0..width {
+                texels.push(
+                    (at(2 * i, 2 * j)
+                        + at(2 * i + 1, 2 * j)
+                        + at(2 * i, 2 * j + 1)
+                        + at(2 * i + 1, 2 * j + 1))
+                        * 0.25,
+                );
+            }
+        }
+        Self {
+            width,
+            height,
+            texels,
+        }
+    }
+}
+
+// Colors are sRGB-encoded in 8-bit images and decoded to linear on load; data such as
+// roughness or normal maps is read as is (new_raw). Hits with a footprint are filtered from a
+// mip pyramid built on load.
+pub struct ImageTexture {
+    levels: Vec<MipLevel>, // the full image first, down to 1 x 1
+    pub filter: TextureFilter,
+    pub mip: MipFilter,
+    pub wrap: WrapMode,
+    pub transform: UvTransform,
+}
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Vec3) -> Vec3 {
+        let (u, v) = self.transform.apply(u, v);
+        self.lookup(0, u, v)
+    }
+    fn filtered(&self, u: f64, v: f64, p: Vec3, footprint: &Footprint) -> Vec3 {
+        if self.mip == MipFilter::None || footprint.is_point() {
+            return self.value(u, v, p);
+        }
+        let (u, v) = self.transform.apply(u, v);
+        // the footprint in texels of the full image, rows downwards
+        let (w, h) = (self.levels[0].width as f64, self.levels[0].height as f64);
+        let (dudx, dvdx) = self.transform.apply_vector(footprint.dudx, footprint.dvdx);
+        let (dudy, dvdy) = self.transform.apply_vector(footprint.dudy, footprint.dvdy);
+        let dst0 = (dudx * w, -dvdx * h);
+        let dst1 = (dudy * w, -dvdy * h);
+        let top = (self.levels.len() - 1) as f64;
+        match self.mip {
+            MipFilter::Ewa => self.ewa(u, v, dst0, dst1),
+            _ => {
+                let width = 2.0
+                    * dst0
+                        .0
+                        .abs()
+                        .max(dst0.1.abs())
+                        .max(dst1.0.abs())
+                        .max(dst1.1.abs());
+                let lod = clamp(width.max(1e-8).log2(), 0.0, top);
+                let level = lod.floor() as usize;
+                if level as f64 >= top {
+                    return self.lookup(level, u, v);
+                }
+                let t = lod - level as f64;
+                self.lookup(level, u, v) * (1.0 - t) + self.lookup(level + 1, u, v) * t
+            }
+        }
+    }
 }
 impl ImageTexture {
+    // an sRGB color image, or a linear float one from .hdr and .exr files
     pub fn new(inputpath: &str) -> Self {
-        let img = image::open(&Path::new(inputpath)).unwrap();
-        let width = img.dimensions().0;
-        let height = img.dimensions().1;
+        if is_hdr_image(inputpath) {
+            return Self::new_hdr(inputpath).unwrap();
+        }
+        Self::load(inputpath, |c| {
+            Vec3::new(
+                srgb_to_linear(c[0]),
+                srgb_to_linear(c[1]),
+                srgb_to_linear(c[2]),
+            )
+        })
+    }
+    // data stored in an image: every channel read as is, from [0, 1]
+    pub fn new_raw(inputpath: &str) -> Self {
+        if is_hdr_image(inputpath) {
+            return Self::new_hdr(inputpath).unwrap();
+        }
+        Self::load(inputpath, |c| Vec3::new(c[0], c[1], c[2]))
+    }
+    // the alpha channel as gray, e.g. the opacity of a Cutout; images without one are opaque
+    pub fn new_alpha(inputpath: &str) -> Self {
+        Self::load(inputpath, |c| Vec3::ones() * c[3])
+    }
+
+    // Radiance .hdr or OpenEXR: linear RGB, brighter than 1 where the scene was
+    pub fn new_hdr(inputpath: &str) -> Result<Self, String> {
+        let (width, height, texels) = read_hdr_image(inputpath)?;
+        Ok(Self::new_from_texels(width, height, texels))
+    }
+
+    // decode: the texel from the RGBA values of a pixel in [0, 1]
+    fn load(inputpath: &str, decode: fn([f64; 4]) -> Vec3) -> Self {
+        let img = image::open(Path::new(inputpath)).unwrap();
+        let (width, height) = img.dimensions();
+        // row by row from the top
+        let texels = img
+            .pixels()
+            .map(|(_, _, px)| {
+                let c = [
+                    px[0] as f64 / 255.0,
+                    px[1] as f64 / 255.0,
+                    px[2] as f64 / 255.0,
+                    px[3] as f64 / 255.0,
+                ];
+                decode(c)
+            })
+            .collect();
+        Self::new_from_texels(width as usize, height as usize, texels)
+    }
+
+    // linear texels, row by row from the top
+    pub fn new_from_texels(width: usize, height: usize, texels: Vec<Vec3>) -> Self {
+        assert_eq!(texels.len(), width * height);
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            texels,
+        }];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            levels.push(next);
+        }
+        Self {
+            levels,
+            filter: TextureFilter::Bilinear,
+            mip: MipFilter::Trilinear,
+            wrap: WrapMode::Clamp,
+            transform: UvTransform::identity(),
+        }
+    }
+
+    // the value at (u, v) in one level of the pyramid, reconstructed by filter
+    fn lookup(&self, level: usize, u: f64, v: f64) -> Vec3 {
+        let l = &self.levels[level];
+        // in texels, the centers at the halves
+        let x = u * l.width as f64 - 0.5;
+        let y = (1.0 - v) * l.height as f64 - 0.5;
+        match self.filter {
+            TextureFilter::Nearest => self.texel(level, x.round() as i64, y.round() as i64),
+            TextureFilter::Bilinear => {
+                let (i, j) = (x.floor() as i64, y.floor() as i64);
+                let (s, t) = (x - x.floor(), y - y.floor());
+                (self.texel(level, i, j) * (1.0 - s) + self.texel(level, i + 1, j) * s) * (1.0 - t)
+                    + (self.texel(level, i, j + 1) * (1.0 - s)
+                        + self.texel(level, i + 1, j + 1) * s)
+                        * t
+            }
+            TextureFilter::Bicubic => {
+                let (i, j) = (x.floor() as i64, y.floor() as i64);
+                let (wx, wy) = (catmull_rom(x - x.floor()), catmull_rom(y - y.floor()));
+                let mut sum = Vec3::zero();
+                for (dj, wj) in wy.iter().enumerate() {
+                    for (di, wi) in wx.iter().enumerate() {
+                        sum += self.texel(level, i + di as i64 - 1, j + dj as i64 - 1) * (wi * wj);
+                    }
+                }
+                // the negative lobes overshoot at hard edges
+                Vec3::new(sum.x.max(0.0), sum.y.max(0.0), sum.z.max(0.0))
+            }
+        }
+    }
 
-        Self { img, width, height }
+    // dst0, dst1: the axes of the footprint in texels of the full image
+    fn ewa(&self, u: f64, v: f64, mut dst0: (f64, f64), mut dst1: (f64, f64)) -> Vec3 {
+        let length = |d: (f64, f64)| (d.0 * d.0 + d.1 * d.1).sqrt();
+        if length(dst0) < length(dst1) {
+            std::mem::swap(&mut dst0, &mut dst1);
+        }
+        let major = length(dst0);
+        let mut minor = length(dst1);
+        // too thin an ellipse would need too many texels: widen it
+        if minor * MAX_ANISOTROPY < major && minor > 0.0 {
+            let scale = major / (minor * MAX_ANISOTROPY);
+            dst1 = (dst1.0 * scale, dst1.1 * scale);
+            minor *= scale;
+        }
+        if minor == 0.0 {
+            return self.lookup(0, u, v);
+        }
+        let top = (self.levels.len() - 1) as f64;
+        let lod = clamp(minor.log2(), 0.0, top);
+        let level = lod.floor() as usize;
+        let t = lod - level as f64;
+        let ret = self.ewa_level(level, u, v, dst0, dst1);
+        if t > 0.0 {
+            ret * (1.0 - t) + self.ewa_level(level + 1, u, v, dst0, dst1) * t
+        } else {
+            ret
+        }
+    }
+    fn ewa_level(&self, level: usize, u: f64, v: f64, dst0: (f64, f64), dst1: (f64, f64)) -> Vec3 {
+        if level >= self.levels.len() {
+            return self.texel(self.levels.len() - 1, 0, 0);
+        }
+        let l = &self.levels[level];
+        // to texels of this level
+        let sx = l.width as f64 / self.levels[0].width as f64;
+        let sy = l.height as f64 / self.levels[0].height as f64;
+        let (d0, d1) = ((dst0.0 * sx, dst0.1 * sy), (dst1.0 * sx, dst1.1 * sy));
+        let s = u * l.width as f64 - 0.5;
+        let t = (1.0 - v) * l.height as f64 - 0.5;
+        // the implicit ellipse a s^2 + b s t + c t^2 = 1, a texel wider so it covers one
+        let mut a = d0.1 * d0.1 + d1.1 * d1.1 + 1.0;
+        let mut b = -2.0 * (d0.0 * d0.1 + d1.0 * d1.1);
+        let mut c = d0.0 * d0.0 + d1.0 * d1.0 + 1.0;
+        let inv_f = 1.0 / (a * c - b * b * 0.25);
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+        // its bounding box
+        let det = 4.0 * a * c - b * b;
+        let (s_half, t_half) = (2.0 * (det * c).sqrt() / det, 2.0 * (det * a).sqrt() / det);
+        let (s0, s1) = ((s - s_half).ceil() as i64, (s + s_half).floor() as i64);
+        let (t0, t1) = ((t - t_half).ceil() as i64, (t + t_half).floor() as i64);
+        let mut sum = Vec3::zero();
+        let mut sum_weights = 0.0;
+        for it in t0..=t1 {
+            let tt = it as f64 - t;
+            for is in s0..=s1 {
+                let ss = is as f64 - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1.0 {
+                    // a Gaussian that falls to zero at the edge
+                    let weight = (-2.0 * r2).exp() - (-2.0f64).exp();
+                    sum += self.texel(level, is, it) * weight;
+                    sum_weights += weight;
+                }
+            }
+        }
+        if sum_weights > 0.0 {
+            sum / sum_weights
+        } else {
+            self.lookup(level, u, v)
+        }
+    }
+
+    // the texel at column i, row j (from the top) of a level, wrapped
+    fn texel(&self, level: usize, i: i64, j: i64) -> Vec3 {
+        let l = &self.levels[level];
+        let i = self.wrap_index(i, l.width);
+        let j = self.wrap_index(j, l.height);
+        l.texels[j * l.width + i]
+    }
+    fn wrap_index(&self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        let i = match self.wrap {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                if i < n {
+                    i
+                } else {
+                    2 * n - 1 - i
+                }
+            }
+            WrapMode::Clamp => i.max(0).min(n - 1),
+        };
+        i as usize
     }
 }
 
@@ -0,0 +1,178 @@
+// Thin-film interference
+// A clear layer a few hundred nanometers thick reflects some wavelengths more than others:
+// soap bubbles, oil slicks, heat-tinted metal. The reflectance is the Airy sum of all the
+// reflections inside the film (Born & Wolf, "Principles of Optics", 1.6), per polarization,
+// over a dielectric or a conducting substrate.
+
+use crate::texture::*;
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+use std::ops::{Add, Div, Mul, Sub};
+use std::sync::Arc;
+
+// the wavelengths (nm) the RGB channels are evaluated at, as for the measured metals
+pub const RGB_WAVELENGTHS: [f64; 3] = [650.0, 550.0, 450.0];
+
+#[derive(Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+    fn real(re: f64) -> Self {
+        Self::new(re, 0.0)
+    }
+    fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+    // the principal root
+    fn sqrt(self) -> Self {
+        let r = self.norm_sqr().sqrt();
+        let re = (0.5 * (r + self.re)).max(0.0).sqrt();
+        let im = (0.5 * (r - self.re)).max(0.0).sqrt();
+        Self::new(re, if self.im < 0.0 { -im } else { im })
+    }
+    // e^(i self)
+    fn exp_i(self) -> Self {
+        let m = (-self.im).exp();
+        Self::new(m * self.re.cos(), m * self.re.sin())
+    }
+}
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+impl Div for Complex {
+    type Output = Self;
+    fn div(self, other: Self) -> Self {
+        let d = other.norm_sqr();
+        Self::new(
+            (self.re * other.re + self.im * other.im) / d,
+            (self.im * other.re - self.re * other.im) / d,
+        )
+    }
+}
+
+// reflectance of a film (thickness in nm, real IOR) lying between a medium n1 on the incident
+// side and a substrate n3 + i k3, unpolarized light at lambda (nm)
+pub fn airy_reflectance(
+    cos_i: f64,
+    n1: f64,
+    film_ior: f64,
+    thickness: f64,
+    n3: f64,
+    k3: f64,
+    lambda: f64,
+) -> f64 {
+    let cos_i = cos_i.abs().min(1.0);
+    let sin2 = 1.0 - cos_i * cos_i;
+    let (n1, n2, n3) = (
+        Complex::real(n1),
+        Complex::real(film_ior),
+        Complex::new(n3, k3),
+    );
+    // Snell's law; complex past the critical angle and in the conductor
+    let cos_in = |n: Complex| (Complex::real(1.0) - n1 * n1 * Complex::real(sin2) / (n * n)).sqrt();
+    let (cos1, cos2, cos3) = (Complex::real(cos_i), cos_in(n2), cos_in(n3));
+
+    let r_s = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+        (na * ca - nb * cb) / (na * ca + nb * cb)
+    };
+    let r_p = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+        (nb * ca - na * cb) / (nb * ca + na * cb)
+    };
+    // the phase picked up on one round trip through the film
+    let phase = (Complex::real(4.0 * PI * thickness / lambda) * n2 * cos2).exp_i();
+    let airy = |r12: Complex, r23: Complex| {
+        let t = r23 * phase;
+        ((r12 + t) / (Complex::real(1.0) + r12 * t)).norm_sqr()
+    };
+    0.5 * (airy(r_s(n1, cos1, n2, cos2), r_s(n2, cos2, n3, cos3))
+        + airy(r_p(n1, cos1, n2, cos2), r_p(n2, cos2, n3, cos3)))
+}
+
+// a value given per RGB channel, linearly interpolated at lambda
+pub fn rgb_at(v: Vec3, lambda: f64) -> f64 {
+    let [r, g, b] = RGB_WAVELENGTHS;
+    if lambda >= r {
+        v.x
+    } else if lambda >= g {
+        v.y + (v.x - v.y) * (lambda - g) / (r - g)
+    } else if lambda >= b {
+        v.z + (v.y - v.z) * (lambda - b) / (g - b)
+    } else {
+        v.z
+    }
+}
+
+// a film over a surface: thickness in nm from the first channel of a texture times scale
+pub struct ThinFilm {
+    pub thickness: Arc<dyn Texture>,
+    pub scale: f64,
+    pub ior: f64,
+}
+impl ThinFilm {
+    pub fn new(thickness: f64, ior: f64) -> Self {
+        Self::new_from_texture(Arc::new(SolidColor::new(Vec3::ones())), thickness, ior)
+    }
+    pub fn new_from_texture(thickness: Arc<dyn Texture>, scale: f64, ior: f64) -> Self {
+        Self {
+            thickness,
+            scale,
+            ior,
+        }
+    }
+
+    // return: the reflectance per channel, all equal when the path carries one wavelength
+    // n1: the IOR on the incident side, eta + i k: the substrate's, per channel
+    pub fn reflectance(
+        &self,
+        (u, v, p): (f64, f64, Vec3),
+        cos_i: f64,
+        n1: f64,
+        eta: Vec3,
+        k: Vec3,
+        wavelength: Option<f64>,
+    ) -> Vec3 {
+        let d = self.thickness.value(u, v, p).x.max(0.0) * self.scale;
+        let at = |lambda: f64| {
+            airy_reflectance(
+                cos_i,
+                n1,
+                self.ior,
+                d,
+                rgb_at(eta, lambda),
+                rgb_at(k, lambda),
+                lambda,
+            )
+        };
+        match wavelength {
+            Some(lambda) => Vec3::ones() * at(lambda),
+            None => Vec3::new(
+                at(RGB_WAVELENGTHS[0]),
+                at(RGB_WAVELENGTHS[1]),
+                at(RGB_WAVELENGTHS[2]),
+            ),
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use std::f64::consts::PI;
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, MulAssign, Neg, Sub, SubAssign};
 
 use crate::shared_tools::*;
 
@@ -112,8 +112,11 @@ impl Vec3 {
 
     // used for PDF method
     pub fn rand_cosine_direction() -> Self {
-        let r1 = rand::random::<f64>();
-        let r2 = rand::random::<f64>();
+        Self::cosine_direction(rand::random::<f64>(), rand::random::<f64>())
+    }
+
+    // the cosine-weighted hemisphere around z at r1, r2 in [0, 1)
+    pub fn cosine_direction(r1: f64, r2: f64) -> Self {
         let z = (1.0 - r2).sqrt();
 
         let phi = 2.0 * PI * r1;
@@ -283,6 +286,19 @@ impl Neg for Vec3 {
     }
 }
 
+// the color channels: 0 red, 1 green, 2 blue
+impl Index<usize> for Vec3 {
+    type Output = f64;
+    fn index(&self, i: usize) -> &f64 {
+        match i {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", i),
+        }
+    }
+}
+
 /*
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,598 @@
+// Voxel grids traversed with the Amanatides–Woo 3D DDA
+// Each voxel stores a material index into the palette, 0 means empty
+// The sparse layout groups voxels into 8^3 bricks, and empty bricks are skipped as a whole
+
+use std::fs;
+
+use crate::bvh::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::Ray;
+use crate::texture::Footprint;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+const BRICK: usize = 8;
+const BRICK_VOXELS: usize = BRICK * BRICK * BRICK;
+
+pub enum VoxelStorage {
+    Dense(Vec<u8>),
+    // bricks of BRICK^3 voxels, None for an empty brick
+    Sparse(Vec<Option<Vec<u8>>>),
+}
+
+pub struct VoxelGrid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    pub min_corner: Vec3,
+    pub voxel_size: f64,
+    pub palette: Vec<Arc<dyn Material>>, // indexed by the voxel value
+    storage: VoxelStorage,
+}
+impl Hittable for VoxelGrid {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let o = (r.orig - self.min_corner) / self.voxel_size;
+        let d = r.dir / self.voxel_size;
+        let (t_in, axis_in, t_out, axis_out) = self.clip(o, d)?;
+        let t0 = t_in.max(t_min);
+        let t1 = t_out.min(t_max);
+        if t0 >= t1 {
+            return None;
+        }
+
+        // a ray starting inside a solid (refraction) looks for the way out of it
+        let mut current = if t_min > t_in {
+            let p = o + d * t0;
+            self.get(
+                (p.x.floor().max(0.0) as usize).min(self.nx - 1),
+                (p.y.floor().max(0.0) as usize).min(self.ny - 1),
+                (p.z.floor().max(0.0) as usize).min(self.nz - 1),
+            )
+        } else {
+            0
+        };
+        let dims = [self.nx, self.ny, self.nz];
+
+        let ret = match &self.storage {
+            VoxelStorage::Dense(_) => walk(
+                o,
+                d,
+                1.0,
+                [0; 3],
+                dims,
+                t0,
+                t1,
+                axis_in,
+                |cell, t, _, axis| self.visit(r, o, d, cell, t, axis, &mut current),
+            ),
+            VoxelStorage::Sparse(bricks) => {
+                let brick_dims = self.brick_dims();
+                walk(
+                    o,
+                    d,
+                    BRICK as f64,
+                    [0; 3],
+                    brick_dims,
+                    t0,
+                    t1,
+                    axis_in,
+                    |brick, t, t_exit, axis| {
+                        let idx = (brick[2] * brick_dims[1] + brick[1]) * brick_dims[0] + brick[0];
+                        if bricks[idx].is_none() && current == 0 {
+                            return None;
+                        }
+                        let lo = [brick[0] * BRICK, brick[1] * BRICK, brick[2] * BRICK];
+                        let hi = [
+                            (lo[0] + BRICK).min(self.nx),
+                            (lo[1] + BRICK).min(self.ny),
+                            (lo[2] + BRICK).min(self.nz),
+                        ];
+                        walk(o, d, 1.0, lo, hi, t, t_exit, axis, |cell, t, _, axis| {
+                            self.visit(r, o, d, cell, t, axis, &mut current)
+                        })
+                    },
+                )
+            }
+        };
+        if ret.is_some() {
+            return ret;
+        }
+        // still inside a solid when leaving the grid
+        if current != 0 && t_out <= t_max {
+            let step = if dir_component(d, axis_out) > 0.0 {
+                1.0
+            } else {
+                -1.0
+            };
+            let rec = self.record(r, o, d, t_out, axis_out, step, current);
+            if rec.mat_ptr.opaque(&rec) {
+                return Some(rec);
+            }
+        }
+        None
+    }
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(AABB::new(
+            self.min_corner,
+            self.min_corner
+                + Vec3::new(self.nx as f64, self.ny as f64, self.nz as f64) * self.voxel_size,
+        ))
+    }
+}
+impl VoxelGrid {
+    pub fn new_dense(
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        min_corner: Vec3,
+        voxel_size: f64,
+        palette: Vec<Arc<dyn Material>>,
+    ) -> Self {
+        assert!(
+            nx > 0 && ny > 0 && nz > 0,
+            "a voxel grid needs at least one voxel"
+        );
+        // entry 0 stands for empty voxels and is never drawn
+        assert!(!palette.is_empty(), "the palette needs the empty entry 0");
+        Self {
+            nx,
+            ny,
+            nz,
+            min_corner,
+            voxel_size,
+            palette,
+            storage: VoxelStorage::Dense(vec![0; nx * ny * nz]),
+        }
+    }
+
+    pub fn new_sparse(
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        min_corner: Vec3,
+        voxel_size: f64,
+        palette: Vec<Arc<dyn Material>>,
+    ) -> Self {
+        let mut ret = Self::new_dense(nx, ny, nz, min_corner, voxel_size, palette);
+        let [bx, by, bz] = ret.brick_dims();
+        ret.storage = VoxelStorage::Sparse(vec![None; bx * by * bz]);
+        ret
+    }
+
+    // load a MagicaVoxel .vox file into a sparse grid, one Lambertian per palette color
+    // MagicaVoxel is z-up, the grid is y-up
+    pub fn new_from_vox(
+        inputpath: &str,
+        min_corner: Vec3,
+        voxel_size: f64,
+    ) -> Result<Self, String> {
+        let data = fs::read(inputpath).map_err(|e| format!("{}: {}", inputpath, e))?;
+        let model = VoxModel::parse(&data)?;
+        let palette = model
+            .palette
+            .iter()
+            .map(|c| Arc::new(Lambertian::new(*c)) as Arc<dyn Material>)
+            .collect();
+        let (sx, sy, sz) = model.size;
+        let mut ret = Self::new_sparse(sx, sz, sy, min_corner, voxel_size, palette);
+        for (x, y, z, idx) in model.voxels {
+            ret.set(x, z, sy - 1 - y, idx);
+        }
+        Ok(ret)
+    }
+
+    fn brick_dims(&self) -> [usize; 3] {
+        [
+            (self.nx - 1) / BRICK + 1,
+            (self.ny - 1) / BRICK + 1,
+            (self.nz - 1) / BRICK + 1,
+        ]
+    }
+
+    // return: (brick index, voxel index inside the brick)
+    fn brick_index(&self, x: usize, y: usize, z: usize) -> (usize, usize) {
+        let [bx, by, _] = self.brick_dims();
+        let brick = ((z / BRICK) * by + y / BRICK) * bx + x / BRICK;
+        let inner = ((z % BRICK) * BRICK + y % BRICK) * BRICK + x % BRICK;
+        (brick, inner)
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> u8 {
+        match &self.storage {
+            VoxelStorage::Dense(data) => data[(z * self.ny + y) * self.nx + x],
+            VoxelStorage::Sparse(bricks) => {
+                let (brick, inner) = self.brick_index(x, y, z);
+                bricks[brick].as_ref().map_or(0, |b| b[inner])
+            }
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: u8) {
+        assert!(
+            (value as usize) < self.palette.len(),
+            "voxel value {} is not in the palette",
+            value
+        );
+        let (brick, inner) = self.brick_index(x, y, z);
+        let idx = (z * self.ny + y) * self.nx + x;
+        match &mut self.storage {
+            VoxelStorage::Dense(data) => data[idx] = value,
+            VoxelStorage::Sparse(bricks) => {
+                if bricks[brick].is_none() {
+                    if value == 0 {
+                        return;
+                    }
+                    bricks[brick] = Some(vec![0; BRICK_VOXELS]);
+                }
+                if let Some(b) = &mut bricks[brick] {
+                    b[inner] = value;
+                }
+            }
+        }
+    }
+
+    // slab test in voxel space, return: (t_in, entry axis, t_out, exit axis)
+    fn clip(&self, o: Vec3, d: Vec3) -> Option<(f64, usize, f64, usize)> {
+        let dims = [self.nx as f64, self.ny as f64, self.nz as f64];
+        let (mut t_in, mut axis_in) = (f64::MIN, 0);
+        let (mut t_out, mut axis_out) = (f64::MAX, 0);
+        for (a, dim) in dims.iter().enumerate() {
+            let invd = 1.0 / dir_component(d, a);
+            let mut t0 = (0.0 - dir_component(o, a)) * invd;
+            let mut t1 = (dim - dir_component(o, a)) * invd;
+            if invd < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            if t0 > t_in {
+                t_in = t0;
+                axis_in = a;
+            }
+            if t1 < t_out {
+                t_out = t1;
+                axis_out = a;
+            }
+        }
+        if t_out <= t_in {
+            None
+        } else {
+            Some((t_in, axis_in, t_out, axis_out))
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn visit(
+        &self,
+        r: &Ray,
+        o: Vec3,
+        d: Vec3,
+        cell: [usize; 3],
+        t: f64,
+        axis: usize,
+        current: &mut u8,
+    ) -> Option<HitRecord> {
+        let value = self.get(cell[0], cell[1], cell[2]);
+        if value == *current {
+            return None;
+        }
+        let step = if dir_component(d, axis) > 0.0 {
+            1.0
+        } else {
+            -1.0
+        };
+        // leaving the solid we are in
+        if *current != 0 {
+            let rec = self.record(r, o, d, t, axis, step, *current);
+            if rec.mat_ptr.opaque(&rec) {
+                return Some(rec);
+            }
+        }
+        // faces the material lets through are walked past, into the next solid or the open
+        *current = value;
+        // entering a solid: its outward normal faces the ray
+        if value != 0 {
+            let rec = self.record(r, o, d, t, axis, -step, value);
+            if rec.mat_ptr.opaque(&rec) {
+                return Some(rec);
+            }
+        }
+        None
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record(
+        &self,
+        r: &Ray,
+        o: Vec3,
+        d: Vec3,
+        t: f64,
+        axis: usize,
+        normal_sign: f64,
+        value: u8,
+    ) -> HitRecord {
+        let mut outward_normal = Vec3::zero();
+        match axis {
+            0 => outward_normal.x = normal_sign,
+            1 => outward_normal.y = normal_sign,
+            _ => outward_normal.z = normal_sign,
+        }
+        // per-face UVs: the position inside the voxel face
+        let pg = o + d * t;
+        let frac = |x: f64| x - x.floor();
+        let (u, v) = match axis {
+            0 => (frac(pg.z), frac(pg.y)),
+            1 => (frac(pg.x), frac(pg.z)),
+            _ => (frac(pg.x), frac(pg.y)),
+        };
+        let mut ret = HitRecord {
+            t,
+            p: r.at(t),
+            normal: outward_normal,
+            front_face: false,
+            tangent: Vec3::zero(),
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            footprint: Footprint::point(),
+            mat_ptr: self.palette[value as usize].clone(),
+            u,
+            v,
+        };
+        ret.set_face_normal(r, &outward_normal);
+        ret
+    }
+}
+
+fn dir_component(v: Vec3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+// Amanatides–Woo walk over the cells in [lo, hi) between t0 and t1
+// o, d: the ray in voxel units; cell_size: the cell edge in voxels
+// visit(cell, t_enter, t_exit, entry axis) returns Some to stop the walk
+#[allow(clippy::too_many_arguments)]
+fn walk<F>(
+    o: Vec3,
+    d: Vec3,
+    cell_size: f64,
+    lo: [usize; 3],
+    hi: [usize; 3],
+    t0: f64,
+    t1: f64,
+    entry_axis: usize,
+    mut visit: F,
+) -> Option<HitRecord>
+where
+    F: FnMut([usize; 3], f64, f64, usize) -> Option<HitRecord>,
+{
+    let mut cell = [0; 3];
+    let mut step = [0; 3];
+    let mut delta = [f64::MAX; 3];
+    let mut next = [f64::MAX; 3];
+    for a in 0..3 {
+        let oa = dir_component(o, a) / cell_size;
+        let da = dir_component(d, a) / cell_size;
+        let x = oa + da * t0;
+        cell[a] = (x.floor().max(lo[a] as f64) as usize).min(hi[a] - 1);
+        if da > 0.0 {
+            step[a] = 1;
+            delta[a] = 1.0 / da;
+            next[a] = (cell[a] as f64 + 1.0 - oa) / da;
+        } else if da < 0.0 {
+            step[a] = -1;
+            delta[a] = -1.0 / da;
+            next[a] = (cell[a] as f64 - oa) / da;
+        }
+    }
+
+    let mut t = t0;
+    let mut axis = entry_axis;
+    while t < t1 {
+        let a = if next[0] < next[1] {
+            if next[0] < next[2] {
+                0
+            } else {
+                2
+            }
+        } else if next[1] < next[2] {
+            1
+        } else {
+            2
+        };
+        let t_exit = next[a].min(t1);
+        if let Some(rec) = visit(cell, t, t_exit, axis) {
+            return Some(rec);
+        }
+
+        t = next[a];
+        axis = a;
+        next[a] += delta[a];
+        if step[a] > 0 {
+            cell[a] += 1;
+            if cell[a] >= hi[a] {
+                break;
+            }
+        } else {
+            if cell[a] == lo[a] {
+                break;
+            }
+            cell[a] -= 1;
+        }
+    }
+    None
+}
+
+// MagicaVoxel's default palette for files without an RGBA chunk, 0xAABBGGRR and indexed by
+// the voxel value: the web-safe color cube without black, then ramps of red, green, blue and gray
+const DEFAULT_PALETTE: [u32; 256] = [
+    0x00000000, 0xffffffff, 0xffccffff, 0xff99ffff, 0xff66ffff, 0xff33ffff, 0xff00ffff, 0xffffccff,
+    0xffccccff, 0xff99ccff, 0xff66ccff, 0xff33ccff, 0xff00ccff, 0xffff99ff, 0xffcc99ff, 0xff9999ff,
+    0xff6699ff, 0xff3399ff, 0xff0099ff, 0xffff66ff, 0xffcc66ff, 0xff9966ff, 0xff6666ff, 0xff3366ff,
+    0xff0066ff, 0xffff33ff, 0xffcc33ff, 0xff9933ff, 0xff6633ff, 0xff3333ff, 0xff0033ff, 0xffff00ff,
+    0xffcc00ff, 0xff9900ff, 0xff6600ff, 0xff3300ff, 0xff0000ff, 0xffffffcc, 0xffccffcc, 0xff99ffcc,
+    0xff66ffcc, 0xff33ffcc, 0xff00ffcc, 0xffffcccc, 0xffcccccc, 0xff99cccc, 0xff66cccc, 0xff33cccc,
+    0xff00cccc, 0xffff99cc, 0xffcc99cc, 0xff9999cc, 0xff6699cc, 0xff3399cc, 0xff0099cc, 0xffff66cc,
+    0xffcc66cc, 0xff9966cc, 0xff6666cc, 0xff3366cc, 0xff0066cc, 0xffff33cc, 0xffcc33cc, 0xff9933cc,
+    0xff6633cc, 0xff3333cc, 0xff0033cc, 0xffff00cc, 0xffcc00cc, 0xff9900cc, 0xff6600cc, 0xff3300cc,
+    0xff0000cc, 0xffffff99, 0xffccff99, 0xff99ff99, 0xff66ff99, 0xff33ff99, 0xff00ff99, 0xffffcc99,
+    0xffcccc99, 0xff99cc99, 0xff66cc99, 0xff33cc99, 0xff00cc99, 0xffff9999, 0xffcc9999, 0xff999999,
+    0xff669999, 0xff339999, 0xff009999, 0xffff6699, 0xffcc6699, 0xff996699, 0xff666699, 0xff336699,
+    0xff006699, 0xffff3399, 0xffcc3399, 0xff993399, 0xff663399, 0xff333399, 0xff003399, 0xffff0099,
+    0xffcc0099, 0xff990099, 0xff660099, 0xff330099, 0xff000099, 0xffffff66, 0xffccff66, 0xff99ff66,
+    0xff66ff66, 0xff33ff66, 0xff00ff66, 0xffffcc66, 0xffcccc66, 0xff99cc66, 0xff66cc66, 0xff33cc66,
+    0xff00cc66, 0xffff9966, 0xffcc9966, 0xff999966, 0xff669966, 0xff339966, 0xff009966, 0xffff6666,
+    0xffcc6666, 0xff996666, 0xff666666, 0xff336666, 0xff006666, 0xffff3366, 0xffcc3366, 0xff993366,
+    0xff663366, 0xff333366, 0xff003366, 0xffff0066, 0xffcc0066, 0xff990066, 0xff660066, 0xff330066,
+    0xff000066, 0xffffff33, 0xffccff33, 0xff99ff33, 0xff66ff33, 0xff33ff33, 0xff00ff33, 0xffffcc33,
+    0xffcccc33, 0xff99cc33, 0xff66cc33, 0xff33cc33, 0xff00cc33, 0xffff9933, 0xffcc9933, 0xff999933,
+    0xff669933, 0xff339933, 0xff009933, 0xffff6633, 0xffcc6633, 0xff996633, 0xff666633, 0xff336633,
+    0xff006633, 0xffff3333, 0xffcc3333, 0xff993333, 0xff663333, 0xff333333, 0xff003333, 0xffff0033,
+    0xffcc0033, 0xff990033, 0xff660033, 0xff330033, 0xff000033, 0xffffff00, 0xffccff00, 0xff99ff00,
+    0xff66ff00, 0xff33ff00, 0xff00ff00, 0xffffcc00, 0xffcccc00, 0xff99cc00, 0xff66cc00, 0xff33cc00,
+    0xff00cc00, 0xffff9900, 0xffcc9900, 0xff999900, 0xff669900, 0xff339900, 0xff009900, 0xffff6600,
+    0xffcc6600, 0xff996600, 0xff666600, 0xff336600, 0xff006600, 0xffff3300, 0xffcc3300, 0xff993300,
+    0xff663300, 0xff333300, 0xff003300, 0xffff0000, 0xffcc0000, 0xff990000, 0xff660000, 0xff330000,
+    0xff0000ee, 0xff0000dd, 0xff0000bb, 0xff0000aa, 0xff000088, 0xff000077, 0xff000055, 0xff000044,
+    0xff000022, 0xff000011, 0xff00ee00, 0xff00dd00, 0xff00bb00, 0xff00aa00, 0xff008800, 0xff007700,
+    0xff005500, 0xff004400, 0xff002200, 0xff001100, 0xffee0000, 0xffdd0000, 0xffbb0000, 0xffaa0000,
+    0xff880000, 0xff770000, 0xff550000, 0xff440000, 0xff220000, 0xff110000, 0xffeeeeee, 0xffdddddd,
+    0xffbbbbbb, 0xffaaaaaa, 0xff888888, 0xff777777, 0xff555555, 0xff444444, 0xff222222, 0xff111111,
+];
+
+// the first model of a MagicaVoxel file
+struct VoxModel {
+    size: (usize, usize, usize),
+    voxels: Vec<(usize, usize, usize, u8)>,
+    palette: Vec<Vec3>, // 256 entries, entry 0 unused
+}
+impl VoxModel {
+    fn parse(data: &[u8]) -> Result<Self, String> {
+        let read_u32 = |pos: usize| -> Result<usize, String> {
+            data.get(pos..pos + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+                .ok_or_else(|| "unexpected end of .vox file".to_string())
+        };
+        if data.len() < 8 || &data[0..4] != b"VOX " {
+            return Err("not a MagicaVoxel file".to_string());
+        }
+        // skip the header and the MAIN chunk header
+        if data.get(8..12) != Some(&b"MAIN"[..]) {
+            return Err("missing MAIN chunk".to_string());
+        }
+        let mut pos = 20 + read_u32(12)?;
+
+        let mut size = None;
+        let mut voxels = None;
+        let mut palette = None;
+        while pos + 12 <= data.len() {
+            let id = &data[pos..pos + 4];
+            let content = pos + 12;
+            let content_size = read_u32(pos + 4)?;
+            let children_size = read_u32(pos + 8)?;
+            match id {
+                b"SIZE" if size.is_none() => {
+                    let dims = (
+                        read_u32(content)?,
+                        read_u32(content + 4)?,
+                        read_u32(content + 8)?,
+                    );
+                    if dims.0 == 0 || dims.1 == 0 || dims.2 == 0 {
+                        return Err("empty model in .vox file".to_string());
+                    }
+                    size = Some(dims);
+                }
+                b"XYZI" if voxels.is_none() => {
+                    let n = read_u32(content)?;
+                    let bytes = data
+                        .get(content + 4..content + 4 + 4 * n)
+                        .ok_or_else(|| "unexpected end of .vox file".to_string())?;
+                    voxels = Some(
+                        bytes
+                            .chunks(4)
+                            .map(|v| (v[0] as usize, v[1] as usize, v[2] as usize, v[3]))
+                            .collect::<Vec<_>>(),
+                    );
+                }
+                b"RGBA" => {
+                    let bytes = data
+                        .get(content..content + 1024)
+                        .ok_or_else(|| "unexpected end of .vox file".to_string())?;
+                    // palette entry i holds the color of index i + 1
+                    let mut colors = vec![Vec3::zero()];
+                    for c in bytes.chunks(4).take(255) {
+                        colors.push(Vec3::new(c[0] as f64, c[1] as f64, c[2] as f64) / 255.0);
+                    }
+                    palette = Some(colors);
+                }
+                _ => {}
+            }
+            pos = content + content_size + children_size;
+        }
+
+        let size = size.ok_or_else(|| "missing SIZE chunk".to_string())?;
+        let voxels = voxels.ok_or_else(|| "missing XYZI chunk".to_string())?;
+        if voxels
+            .iter()
+            .any(|&(x, y, z, _)| x >= size.0 || y >= size.1 || z >= size.2)
+        {
+            return Err("voxel outside the model size in .vox file".to_string());
+        }
+        let palette = palette.unwrap_or_else(|| {
+            DEFAULT_PALETTE
+                .iter()
+                .map(|c| {
+                    let channel = |shift: u32| ((c >> shift) & 0xff) as f64 / 255.0;
+                    Vec3::new(channel(0), channel(8), channel(16))
+                })
+                .collect()
+        });
+        Ok(Self {
+            size,
+            voxels,
+            palette,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut ret = id.to_vec();
+        ret.extend(&(content.len() as u32).to_le_bytes());
+        ret.extend(&(children.len() as u32).to_le_bytes());
+        ret.extend(content);
+        ret.extend(children);
+        ret
+    }
+
+    #[test]
+    fn default_palette() {
+        let mut size = Vec::new();
+        for n in &[2u32, 1, 1] {
+            size.extend(&n.to_le_bytes());
+        }
+        let mut xyzi = 2u32.to_le_bytes().to_vec();
+        xyzi.extend(&[0, 0, 0, 36, 1, 0, 0, 216]);
+        let mut children = chunk(b"SIZE", &size, &[]);
+        children.extend(chunk(b"XYZI", &xyzi, &[]));
+        let mut data = b"VOX ".to_vec();
+        data.extend(&150u32.to_le_bytes());
+        data.extend(chunk(b"MAIN", &[], &children));
+
+        let model = VoxModel::parse(&data).unwrap();
+        assert_eq!(model.voxels, vec![(0, 0, 0, 36), (1, 0, 0, 216)]);
+        assert_eq!(model.palette.len(), 256);
+        assert_eq!(model.palette[1], Vec3::ones());
+        // the last color of the cube is pure red, the first of the red ramp a darker one
+        assert_eq!(model.palette[36], Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(model.palette[216], Vec3::new(238.0 / 255.0, 0.0, 0.0));
+        assert_eq!(model.palette[255], Vec3::ones() * (17.0 / 255.0));
+    }
+}